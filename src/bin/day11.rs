@@ -1,20 +1,33 @@
 use advent_of_code::execution_time;
-use anyhow::Result;
+use anyhow::{Context, Result};
+use hashbrown::{HashMap, HashSet};
+use itertools::Itertools;
+use num::{CheckedAdd, CheckedMul, Integer};
+use std::fmt::{self, Display};
+use std::hash::Hash;
 use std::mem;
 
-#[derive(Debug, Clone, Copy)]
+/// A number that can hold an item's worry level, e.g. `u64`, `u128` or `BigUint`
+pub trait WorryLevel: Integer + CheckedAdd + CheckedMul + From<u64> + Clone {}
+
+impl<T> WorryLevel for T where T: Integer + CheckedAdd + CheckedMul + From<u64> + Clone {}
+
+#[derive(Debug, Clone)]
 pub enum Operation {
-    Add(u64),
-    Multiply(u64),
-    Square,
+    Old,
+    Constant(u64),
+    Add(Box<Operation>, Box<Operation>),
+    Multiply(Box<Operation>, Box<Operation>),
 }
 
 impl Operation {
-    pub fn run(self, old: u64) -> u64 {
+    /// Returns `None` if the worry level overflows
+    pub fn run<T: WorryLevel>(&self, old: &T) -> Option<T> {
         match self {
-            Operation::Add(other) => old + other,
-            Operation::Multiply(other) => old * other,
-            Operation::Square => old * old,
+            Operation::Old => Some(old.clone()),
+            Operation::Constant(value) => Some(T::from(*value)),
+            Operation::Add(a, b) => a.run(old)?.checked_add(&b.run(old)?),
+            Operation::Multiply(a, b) => a.run(old)?.checked_mul(&b.run(old)?),
         }
     }
 }
@@ -27,8 +40,8 @@ pub struct Rule {
 }
 
 impl Rule {
-    pub fn test(self, item: u64) -> usize {
-        if item % self.divisor == 0 {
+    pub fn test<T: WorryLevel>(self, item: &T) -> usize {
+        if item.is_multiple_of(&T::from(self.divisor)) {
             self.success
         } else {
             self.fail
@@ -37,21 +50,100 @@ impl Rule {
 }
 
 #[derive(Debug)]
-pub struct Monkey {
+pub struct Monkey<T = u64> {
     pub id: usize,
-    pub items: Vec<u64>,
+    pub items: Vec<T>,
     pub operation: Operation,
     pub rule: Rule,
     pub inspections: u64,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationError {
+    ThrowTargetOutOfRange { monkey: usize, target: usize },
+    SelfThrow { monkey: usize },
+    DuplicateId { id: usize },
+    NonContiguousId { index: usize, id: usize },
+    ZeroDivisor { monkey: usize },
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValidationError::ThrowTargetOutOfRange { monkey, target } => {
+                write!(
+                    f,
+                    "Monkey {monkey} throws to monkey {target}, which doesn't exist"
+                )
+            }
+            ValidationError::SelfThrow { monkey } => {
+                write!(f, "Monkey {monkey} throws to itself")
+            }
+            ValidationError::DuplicateId { id } => write!(f, "Monkey {id} is defined twice"),
+            ValidationError::NonContiguousId { index, id } => {
+                write!(f, "Monkey {id} is defined at position {index}")
+            }
+            ValidationError::ZeroDivisor { monkey } => {
+                write!(f, "Monkey {monkey} tests divisibility by zero")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Makes sure every monkey's id matches its position and every throw lands on another monkey
+pub fn validate<T>(monkeys: &[Monkey<T>]) -> Result<(), ValidationError> {
+    let mut seen_ids = HashSet::new();
+
+    for (index, monkey) in monkeys.iter().enumerate() {
+        if !seen_ids.insert(monkey.id) {
+            return Err(ValidationError::DuplicateId { id: monkey.id });
+        }
+
+        if monkey.id != index {
+            return Err(ValidationError::NonContiguousId {
+                index,
+                id: monkey.id,
+            });
+        }
+    }
+
+    for monkey in monkeys {
+        let Rule {
+            divisor,
+            success,
+            fail,
+        } = monkey.rule;
+
+        if divisor == 0 {
+            return Err(ValidationError::ZeroDivisor { monkey: monkey.id });
+        }
+
+        for target in [success, fail] {
+            if target >= monkeys.len() {
+                return Err(ValidationError::ThrowTargetOutOfRange {
+                    monkey: monkey.id,
+                    target,
+                });
+            }
+
+            if target == monkey.id {
+                return Err(ValidationError::SelfThrow { monkey: monkey.id });
+            }
+        }
+    }
+
+    Ok(())
+}
+
 mod parser {
     use super::*;
     use nom::branch::alt;
     use nom::bytes::complete::tag;
-    use nom::character::complete::{multispace1, newline, space1, u64};
+    use nom::character::complete::{char, multispace1, newline, space0, space1, u64};
     use nom::combinator::{map, value};
-    use nom::multi::separated_list1;
+    use nom::multi::{fold_many0, separated_list1};
     use nom::sequence::{delimited, preceded, terminated};
     use nom::{Finish, IResult};
 
@@ -65,28 +157,42 @@ mod parser {
         map(preceded(prefix, list), Vec::from)(s)
     }
 
-    fn add_operation(s: &str) -> IResult<&str, Operation> {
-        map(preceded(tag("+ "), u64), Operation::Add)(s)
-    }
+    fn factor(s: &str) -> IResult<&str, Operation> {
+        let old = value(Operation::Old, tag("old"));
+        let constant = map(u64, Operation::Constant);
+        let parenthesized = delimited(
+            terminated(char('('), space0),
+            expression,
+            preceded(space0, char(')')),
+        );
 
-    fn multiply_operation(s: &str) -> IResult<&str, Operation> {
-        map(preceded(tag("* "), u64), Operation::Multiply)(s)
+        alt((old, constant, parenthesized))(s)
     }
 
-    fn square_operation(s: &str) -> IResult<&str, Operation> {
-        value(Operation::Square, tag("* old"))(s)
+    fn term(s: &str) -> IResult<&str, Operation> {
+        let (s, first) = factor(s)?;
+
+        fold_many0(
+            preceded(delimited(space0, char('*'), space0), factor),
+            move || first.clone(),
+            |a, b| Operation::Multiply(Box::new(a), Box::new(b)),
+        )(s)
     }
 
-    fn operation_prefix(s: &str) -> IResult<&str, &str> {
-        preceded(space1, tag("Operation: new = old "))(s)
+    fn expression(s: &str) -> IResult<&str, Operation> {
+        let (s, first) = term(s)?;
+
+        fold_many0(
+            preceded(delimited(space0, char('+'), space0), term),
+            move || first.clone(),
+            |a, b| Operation::Add(Box::new(a), Box::new(b)),
+        )(s)
     }
 
     fn operation(s: &str) -> IResult<&str, Operation> {
-        let add = preceded(operation_prefix, add_operation);
-        let multiply = preceded(operation_prefix, multiply_operation);
-        let square = preceded(operation_prefix, square_operation);
+        let prefix = preceded(space1, tag("Operation: new = "));
 
-        alt((add, multiply, square))(s)
+        preceded(prefix, expression)(s)
     }
 
     fn test_rule(input: &str) -> IResult<&str, Rule> {
@@ -109,7 +215,7 @@ mod parser {
         Ok((input, rule))
     }
 
-    fn monkey(input: &str) -> IResult<&str, Monkey> {
+    fn monkey<T: WorryLevel>(input: &str) -> IResult<&str, Monkey<T>> {
         let (input, id) = terminated(id, newline)(input)?;
         let (input, items) = terminated(items, newline)(input)?;
         let (input, operation) = terminated(operation, newline)(input)?;
@@ -117,7 +223,7 @@ mod parser {
 
         let monkey = Monkey {
             id,
-            items,
+            items: items.into_iter().map(T::from).collect(),
             operation,
             rule,
             inspections: 0,
@@ -126,82 +232,393 @@ mod parser {
         Ok((input, monkey))
     }
 
-    pub fn parse(input: &'static str) -> Result<Vec<Monkey>> {
+    pub fn parse<T: WorryLevel>(input: &'static str) -> Result<Vec<Monkey<T>>> {
         let result = separated_list1(tag("\n\n"), monkey)(input);
 
         let (_, monkeys) = result.finish()?;
+        validate(&monkeys)?;
 
         Ok(monkeys)
     }
 }
 
-fn solve_part1(input: &'static str) -> u64 {
-    let mut monkeys = parser::parse(input).unwrap();
+/// How worry levels are kept in check after a monkey inspects an item
+pub trait Relief<T> {
+    fn relieve(&self, item: T) -> T;
+}
+
+/// The worry level is divided by a constant, e.g. by three in part 1
+pub struct Divide<T>(pub T);
+
+impl<T: WorryLevel> Relief<T> for Divide<T> {
+    fn relieve(&self, item: T) -> T {
+        item / self.0.clone()
+    }
+}
+
+/// The worry level is reduced modulo a multiple of every divisor, which keeps every test intact
+pub struct Modulo<T>(pub T);
+
+impl<T: WorryLevel> Relief<T> for Modulo<T> {
+    fn relieve(&self, item: T) -> T {
+        item % self.0.clone()
+    }
+}
+
+/// The state of every monkey after a round
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoundSnapshot<T> {
+    pub round: usize,
+    pub items: Vec<Vec<T>>,
+    pub inspections: Vec<u64>,
+}
+
+pub struct MonkeyBusiness<T, R> {
+    pub monkeys: Vec<Monkey<T>>,
+    pub relief: R,
+    pub round: usize,
+}
+
+impl<T: WorryLevel, R: Relief<T>> MonkeyBusiness<T, R> {
+    pub fn new(monkeys: Vec<Monkey<T>>, relief: R) -> Self {
+        Self {
+            monkeys,
+            relief,
+            round: 0,
+        }
+    }
+
+    pub fn run_round(&mut self) -> Result<()> {
+        let round = self.round + 1;
 
-    for _round in 0..20 {
-        for monkey_id in 0..monkeys.len() {
-            let items = mem::take(&mut monkeys[monkey_id].items);
+        for monkey_id in 0..self.monkeys.len() {
+            let items = mem::take(&mut self.monkeys[monkey_id].items);
 
-            for mut item in items {
-                monkeys[monkey_id].inspections += 1;
-                item = monkeys[monkey_id].operation.run(item);
+            for item in items {
+                let monkey = &mut self.monkeys[monkey_id];
 
-                item /= 3;
+                monkey.inspections += 1;
+                let item = monkey.operation.run(&item).with_context(|| {
+                    format!("Worry level overflowed at monkey {monkey_id} in round {round}")
+                })?;
 
-                let monkey_throw_to = monkeys[monkey_id].rule.test(item);
-                monkeys[monkey_throw_to].items.push(item);
+                let item = self.relief.relieve(item);
+
+                let monkey_throw_to = monkey.rule.test(&item);
+                self.monkeys[monkey_throw_to].items.push(item);
             }
         }
+
+        self.round = round;
+
+        Ok(())
     }
 
-    monkeys.sort_unstable_by_key(|monkey| monkey.inspections);
+    pub fn run(&mut self, rounds: usize) -> Result<()> {
+        for _ in 0..rounds {
+            self.run_round()?;
+        }
 
-    monkeys
-        .iter()
-        .map(|monkey| monkey.inspections)
-        .rev()
-        .take(2)
-        .product()
-}
+        Ok(())
+    }
 
-fn solve_part2(input: &'static str) -> u64 {
-    let mut monkeys = parser::parse(input).unwrap();
+    pub fn snapshot(&self) -> RoundSnapshot<T> {
+        RoundSnapshot {
+            round: self.round,
+            items: self
+                .monkeys
+                .iter()
+                .map(|monkey| monkey.items.clone())
+                .collect(),
+            inspections: self
+                .monkeys
+                .iter()
+                .map(|monkey| monkey.inspections)
+                .collect(),
+        }
+    }
+
+    /// Runs `rounds` rounds, taking a snapshot after each one
+    pub fn snapshots(&mut self, rounds: usize) -> Result<Vec<RoundSnapshot<T>>> {
+        (0..rounds)
+            .map(|_| {
+                self.run_round()?;
+                Ok(self.snapshot())
+            })
+            .collect()
+    }
 
+    /// The product of the inspections of the two most active monkeys
+    pub fn level(&self) -> u64 {
+        let mut inspections = self
+            .monkeys
+            .iter()
+            .map(|monkey| monkey.inspections)
+            .collect_vec();
+        inspections.sort_unstable();
+
+        inspections.iter().rev().take(2).product()
+    }
+}
+
+/// The least common multiple of all divisors, which doesn't change the outcome of any test
+fn divisor_limit<T: WorryLevel>(monkeys: &[Monkey<T>]) -> T {
     let limit = monkeys
         .iter()
         .map(|monkey| monkey.rule.divisor)
-        .product::<u64>();
+        .fold(1, num::integer::lcm::<u64>);
 
-    for _round in 0..10000 {
-        for monkey_id in 0..monkeys.len() {
-            let items = mem::take(&mut monkeys[monkey_id].items);
+    T::from(limit)
+}
+
+/// Follows a single item through the monkeys and returns the inspections per monkey after
+/// `rounds` rounds. The item's `(monkey, worry level)` at the start of a round determines
+/// everything that follows, so once a state repeats the remaining rounds are extrapolated
+fn count_item_inspections<T: WorryLevel + Hash>(
+    monkeys: &[Monkey<T>],
+    limit: &T,
+    mut monkey_id: usize,
+    mut item: T,
+    rounds: u64,
+) -> Result<Vec<u64>> {
+    let mut seen_states = HashMap::new();
+    // Inspections per monkey before each round
+    let mut history = vec![vec![0; monkeys.len()]];
+
+    for round in 0..rounds {
+        if let Some(&cycle_start) = seen_states.get(&(monkey_id, item.clone())) {
+            let cycle_length = round - cycle_start;
+            let remaining_rounds = rounds - round;
+
+            let cycles = remaining_rounds / cycle_length;
+            let leftover = remaining_rounds % cycle_length;
+
+            let current = &history[round as usize];
+            let start = &history[cycle_start as usize];
+            let partial = &history[(cycle_start + leftover) as usize];
+
+            let inspections = (0..monkeys.len())
+                .map(|id| {
+                    current[id] + cycles * (current[id] - start[id]) + partial[id] - start[id]
+                })
+                .collect();
+
+            return Ok(inspections);
+        }
 
-            for mut item in items {
-                monkeys[monkey_id].inspections += 1;
-                item = monkeys[monkey_id].operation.run(item);
+        seen_states.insert((monkey_id, item.clone()), round);
 
-                item %= limit;
+        let mut inspections = history[round as usize].clone();
 
-                let monkey_throw_to = monkeys[monkey_id].rule.test(item);
-                monkeys[monkey_throw_to].items.push(item);
+        loop {
+            let monkey = &monkeys[monkey_id];
+
+            inspections[monkey_id] += 1;
+            item = monkey.operation.run(&item).with_context(|| {
+                format!(
+                    "Worry level overflowed at monkey {monkey_id} in round {}",
+                    round + 1
+                )
+            })? % limit.clone();
+
+            let monkey_throw_to = monkey.rule.test(&item);
+
+            // Monkeys that already had their turn will see the item next round
+            let is_next_round = monkey_throw_to <= monkey_id;
+            monkey_id = monkey_throw_to;
+
+            if is_next_round {
+                break;
             }
         }
+
+        history.push(inspections);
     }
 
-    monkeys.sort_unstable_by_key(|monkey| monkey.inspections);
+    Ok(history.pop().unwrap())
+}
+
+fn solve_part1<T: WorryLevel>(input: &'static str) -> Result<u64> {
+    let monkeys = parser::parse::<T>(input)?;
+
+    let mut monkey_business = MonkeyBusiness::new(monkeys, Divide(T::from(3)));
+    monkey_business.run(20)?;
+
+    Ok(monkey_business.level())
+}
+
+fn solve_part2<T: WorryLevel>(input: &'static str) -> Result<u64> {
+    let monkeys = parser::parse::<T>(input)?;
+    let limit = divisor_limit(&monkeys);
+
+    let mut monkey_business = MonkeyBusiness::new(monkeys, Modulo(limit));
+    monkey_business.run(10000)?;
+
+    Ok(monkey_business.level())
+}
 
-    monkeys
+/// Same as part 2, but tracks every item on its own so any amount of rounds is feasible
+fn solve_part2_extrapolated<T: WorryLevel + Hash>(
+    input: &'static str,
+    rounds: u64,
+) -> Result<u128> {
+    let monkeys = parser::parse::<T>(input)?;
+    let limit = divisor_limit(&monkeys);
+
+    let mut inspections = vec![0; monkeys.len()];
+
+    for (monkey_id, monkey) in monkeys.iter().enumerate() {
+        for item in &monkey.items {
+            let item_inspections =
+                count_item_inspections(&monkeys, &limit, monkey_id, item.clone(), rounds)?;
+
+            for (total, amount) in inspections.iter_mut().zip(item_inspections) {
+                *total += amount;
+            }
+        }
+    }
+
+    inspections.sort_unstable();
+
+    let result = inspections
         .iter()
-        .map(|monkey| monkey.inspections)
         .rev()
         .take(2)
-        .product()
+        .map(|&amount| amount as u128)
+        .product();
+
+    Ok(result)
 }
 
-pub fn main() {
+pub fn main() -> Result<()> {
     let input = include_str!("../input/day11.txt");
 
-    execution_time(|| dbg!(solve_part1(input)));
+    let part_1 = execution_time(|| solve_part1::<u64>(input))?;
+    dbg!(part_1);
+
+    let part_2 = execution_time(|| solve_part2::<u64>(input))?;
+    dbg!(part_2);
+
+    let part_2_extrapolated =
+        execution_time(|| solve_part2_extrapolated::<u64>(input, 1_000_000_000))?;
+    dbg!(part_2_extrapolated);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+Monkey 0:
+  Starting items: 79, 98
+  Operation: new = old * 19
+  Test: divisible by 23
+    If true: throw to monkey 2
+    If false: throw to monkey 3
+
+Monkey 1:
+  Starting items: 54, 65, 75, 74
+  Operation: new = old + 6
+  Test: divisible by 19
+    If true: throw to monkey 2
+    If false: throw to monkey 0
+
+Monkey 2:
+  Starting items: 79, 60, 97
+  Operation: new = old * old
+  Test: divisible by 13
+    If true: throw to monkey 1
+    If false: throw to monkey 3
+
+Monkey 3:
+  Starting items: 74
+  Operation: new = old + 3
+  Test: divisible by 17
+    If true: throw to monkey 0
+    If false: throw to monkey 1
+";
+
+    fn part2_business() -> MonkeyBusiness<u64, Modulo<u64>> {
+        let monkeys = parser::parse::<u64>(EXAMPLE).unwrap();
+        let limit = divisor_limit(&monkeys);
+
+        MonkeyBusiness::new(monkeys, Modulo(limit))
+    }
+
+    #[test]
+    fn first_round_items() {
+        let monkeys = parser::parse::<u64>(EXAMPLE).unwrap();
+        let mut monkey_business = MonkeyBusiness::new(monkeys, Divide(3));
+
+        let snapshots = monkey_business.snapshots(1).unwrap();
+
+        assert_eq!(
+            snapshots[0].items,
+            vec![
+                vec![20, 23, 27, 26],
+                vec![2080, 25, 167, 207, 401, 1046],
+                vec![],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn inspection_counts() {
+        let snapshots = part2_business().snapshots(1000).unwrap();
+
+        assert_eq!(snapshots[0].inspections, vec![2, 4, 3, 6]);
+        assert_eq!(snapshots[19].inspections, vec![99, 97, 8, 103]);
+        assert_eq!(snapshots[999].inspections, vec![5204, 4792, 199, 5192]);
+        assert_eq!(snapshots[999].round, 1000);
+    }
+
+    #[test]
+    fn example_solutions() {
+        assert_eq!(solve_part1::<u64>(EXAMPLE).unwrap(), 10605);
+        assert_eq!(solve_part2::<u64>(EXAMPLE).unwrap(), 2713310158);
+    }
 
-    execution_time(|| dbg!(solve_part2(input)));
+    #[test]
+    fn overflow_rounds_agree() {
+        const OVERFLOWING: &str = "\
+Monkey 0:
+  Starting items: 4294967300
+  Operation: new = old * old
+  Test: divisible by 4294967311
+    If true: throw to monkey 1
+    If false: throw to monkey 1
+
+Monkey 1:
+  Starting items: 1
+  Operation: new = old + 1
+  Test: divisible by 2
+    If true: throw to monkey 0
+    If false: throw to monkey 0
+";
+
+        let expected = "Worry level overflowed at monkey 0 in round 1";
+
+        let error = solve_part2::<u64>(OVERFLOWING).unwrap_err();
+        assert_eq!(error.to_string(), expected);
+
+        let error = solve_part2_extrapolated::<u64>(OVERFLOWING, 10).unwrap_err();
+        assert_eq!(error.to_string(), expected);
+    }
+
+    #[test]
+    fn extrapolation_matches_simulation() {
+        for rounds in [1, 20, 777, 1000, 10000] {
+            let mut monkey_business = part2_business();
+            monkey_business.run(rounds).unwrap();
+
+            assert_eq!(
+                solve_part2_extrapolated::<u64>(EXAMPLE, rounds as u64).unwrap(),
+                monkey_business.level() as u128,
+                "{rounds} rounds"
+            );
+        }
+    }
 }
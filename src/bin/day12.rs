@@ -1,8 +1,13 @@
 use advent_of_code::execution_time;
+use advent_of_code::ppm::Image;
+use anyhow::{bail, ensure, Context, Result};
+use itertools::Itertools;
 use ndarray::Array2;
-use pathfinding::prelude::dijkstra;
-use rayon::prelude::*;
-use vek::Vec2;
+use pathfinding::prelude::{bfs_reach, dijkstra_all};
+use std::collections::VecDeque;
+use std::env;
+use std::fmt::{self, Display};
+use vek::{Rgb, Vec2};
 
 type Elevations = Array2<u8>;
 
@@ -13,43 +18,56 @@ struct ParseResult {
     end: Vec2<usize>,
 }
 
-fn parse_input(input: &str) -> ParseResult {
-    let width = input.lines().next().unwrap().len();
+fn parse_input(input: &str) -> Result<ParseResult> {
+    let width = input.lines().next().context("Empty map")?.len();
     let height = input.lines().count();
 
-    let mut start = Vec2::default();
-    let mut end = Vec2::default();
+    let mut start = None;
+    let mut end = None;
 
     let mut elevations = Array2::default((width, height));
     let mut trail_starts = Vec::new();
 
     for (y, line) in input.lines().enumerate() {
+        ensure!(
+            line.len() == width,
+            "Row {y} is {} wide, expected {width}",
+            line.len()
+        );
+
         for (x, mut elevation) in line.bytes().enumerate() {
+            let position = Vec2::new(x, y);
+
             match elevation {
                 b'a' => {
-                    trail_starts.push(Vec2::new(x, y));
+                    trail_starts.push(position);
                 }
                 b'S' => {
+                    ensure!(start.is_none(), "Second start at {position}");
+
                     elevation = b'a';
-                    start = Vec2::new(x, y);
+                    start = Some(position);
                 }
                 b'E' => {
+                    ensure!(end.is_none(), "Second end at {position}");
+
                     elevation = b'z';
-                    end = Vec2::new(x, y);
+                    end = Some(position);
                 }
-                _ => {}
+                b'b'..=b'z' => {}
+                _ => bail!("Unknown character {:?} at {position}", elevation as char),
             }
 
             elevations[(x, y)] = elevation;
         }
     }
 
-    ParseResult {
+    Ok(ParseResult {
         elevations,
         trail_starts,
-        start,
-        end,
-    }
+        start: start.context("Missing start `S`")?,
+        end: end.context("Missing end `E`")?,
+    })
 }
 
 fn is_in_bounds(bounds: Vec2<usize>, position: Vec2<i32>) -> Option<Vec2<usize>> {
@@ -60,81 +78,388 @@ fn is_in_bounds(bounds: Vec2<usize>, position: Vec2<i32>) -> Option<Vec2<usize>>
         .then(|| (position.as_()))
 }
 
-fn find_shortest_path(
+const NEIGHBOR_OFFSETS: [Vec2<i32>; 4] = [
+    Vec2::new(0, -1),
+    Vec2::new(1, 0),
+    Vec2::new(0, 1),
+    Vec2::new(-1, 0),
+];
+
+const DIAGONAL_OFFSETS: [Vec2<i32>; 4] = [
+    Vec2::new(1, -1),
+    Vec2::new(1, 1),
+    Vec2::new(-1, 1),
+    Vec2::new(-1, -1),
+];
+
+/// How the hiker may move from one position to the next
+#[derive(Clone, Copy)]
+struct ClimbRules {
+    max_ascent: u8,
+    max_descent: u8,
+    allow_diagonal: bool,
+    /// The cost of a step between two elevations, which must be at least 1. Every step costs 1
+    /// if `None`
+    step_cost: Option<fn(u8, u8) -> u32>,
+}
+
+impl Default for ClimbRules {
+    /// The rules from the puzzle: climb at most one higher, descend as far as needed
+    fn default() -> Self {
+        Self {
+            max_ascent: 1,
+            max_descent: u8::MAX,
+            allow_diagonal: false,
+            step_cost: None,
+        }
+    }
+}
+
+impl ClimbRules {
+    fn can_climb(&self, elevation: u8, neighbor_elevation: u8) -> bool {
+        if neighbor_elevation >= elevation {
+            neighbor_elevation - elevation <= self.max_ascent
+        } else {
+            elevation - neighbor_elevation <= self.max_descent
+        }
+    }
+
+    fn cost(&self, elevation: u8, neighbor_elevation: u8) -> u32 {
+        self.step_cost
+            .map_or(1, |step_cost| step_cost(elevation, neighbor_elevation))
+    }
+
+    fn neighbors(
+        &self,
+        bounds: Vec2<usize>,
+        position: Vec2<usize>,
+    ) -> impl Iterator<Item = Vec2<usize>> {
+        let diagonal_offsets = if self.allow_diagonal {
+            &DIAGONAL_OFFSETS[..]
+        } else {
+            &[]
+        };
+
+        NEIGHBOR_OFFSETS
+            .iter()
+            .chain(diagonal_offsets)
+            .flat_map(move |&neighbor_offset| {
+                is_in_bounds(bounds, position.as_() + neighbor_offset)
+            })
+    }
+}
+
+/// Cost of reaching `end` from every position, `None` where `end` can't be reached
+type Distances = Array2<Option<u32>>;
+
+/// Searches backwards from `end`, so every possible start is covered at once. This is a
+/// breadth-first search when every step costs the same, and Dijkstra otherwise
+fn distances_to_end(end: Vec2<usize>, elevations: &Elevations, rules: ClimbRules) -> Distances {
+    let bounds = Vec2::from(elevations.dim());
+
+    // The neighbors which can climb to `position`, and the cost of doing so
+    let predecessors = |&position: &Vec2<usize>| {
+        let elevation = elevations[position.into_tuple()];
+
+        rules
+            .neighbors(bounds, position)
+            .flat_map(move |neighbor_position| {
+                let neighbor_elevation = elevations[neighbor_position.into_tuple()];
+
+                rules
+                    .can_climb(neighbor_elevation, elevation)
+                    .then(|| (neighbor_position, rules.cost(neighbor_elevation, elevation)))
+            })
+    };
+
+    let mut distances = Distances::default(elevations.raw_dim());
+    distances[end.into_tuple()] = Some(0);
+
+    if rules.step_cost.is_some() {
+        for (position, (_, cost)) in dijkstra_all(&end, |position| predecessors(position)) {
+            distances[position.into_tuple()] = Some(cost);
+        }
+
+        return distances;
+    }
+
+    let mut queue = VecDeque::from([end]);
+
+    while let Some(position) = queue.pop_front() {
+        let steps = distances[position.into_tuple()].unwrap() + 1;
+
+        for (neighbor_position, _) in predecessors(&position) {
+            let neighbor_distance = &mut distances[neighbor_position.into_tuple()];
+
+            if neighbor_distance.is_none() {
+                *neighbor_distance = Some(steps);
+                queue.push_back(neighbor_position);
+            }
+        }
+    }
+
+    distances
+}
+
+/// Follows decreasing distances from `start`, returning every position along the way up to the end
+fn find_path(
     start: Vec2<usize>,
-    end: Vec2<usize>,
     elevations: &Elevations,
-) -> Option<u32> {
+    distances: &Distances,
+    rules: ClimbRules,
+) -> Option<Vec<Vec2<usize>>> {
     let bounds = Vec2::from(elevations.dim());
 
-    let (_, steps) = dijkstra(
-        &start,
-        |&position| {
-            let elevation = elevations[position.into_tuple()];
+    let mut position = start;
+    let mut path = vec![position];
+
+    while let Some(distance @ 1..) = distances[position.into_tuple()] {
+        let elevation = elevations[position.into_tuple()];
+
+        position = rules
+            .neighbors(bounds, position)
+            .find(|&neighbor_position| {
+                let neighbor_elevation = elevations[neighbor_position.into_tuple()];
+
+                let remaining_distance =
+                    distance.checked_sub(rules.cost(elevation, neighbor_elevation));
+
+                rules.can_climb(elevation, neighbor_elevation)
+                    && distances[neighbor_position.into_tuple()] == remaining_distance
+            })?;
+
+        path.push(position);
+    }
+
+    distances[position.into_tuple()].map(|_| path)
+}
+
+/// Draws the path with arrows like in the puzzle description
+fn render_path(path: &[Vec2<usize>], (width, height): (usize, usize)) -> String {
+    let mut tiles = Array2::from_elem((width, height), '.');
 
-            const NEIGHBOR_OFFSETS: [Vec2<i32>; 4] = [
-                Vec2::new(0, -1),
-                Vec2::new(1, 0),
-                Vec2::new(0, 1),
-                Vec2::new(-1, 0),
-            ];
+    for (&position, &next_position) in path.iter().zip(path.iter().skip(1)) {
+        let direction = next_position.as_::<i32>() - position.as_::<i32>();
 
-            NEIGHBOR_OFFSETS.iter().flat_map(move |&neighbor_offset| {
-                let neighbor_position = position.as_::<i32>() + neighbor_offset;
+        tiles[position.into_tuple()] = match direction.into_tuple() {
+            (1, 0) => '>',
+            (0, 1) => 'v',
+            (-1, 0) => '<',
+            (0, -1) => '^',
+            (1, 1) | (-1, -1) => '\\',
+            (1, -1) | (-1, 1) => '/',
+            _ => unreachable!(),
+        };
+    }
+
+    if let Some(&end) = path.last() {
+        tiles[end.into_tuple()] = 'E';
+    }
+
+    (0..height)
+        .map(|y| (0..width).map(|x| tiles[(x, y)]).collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Shades the map by elevation, with red showing how close a position is to the end, and the
+/// path in white. Positions the end can't be reached from are gray
+fn render_heat_map(elevations: &Elevations, distances: &Distances, path: &[Vec2<usize>]) -> Image {
+    let (width, height) = elevations.dim();
+    let max_distance = distances
+        .iter()
+        .flatten()
+        .max()
+        .copied()
+        .unwrap_or(0)
+        .max(1);
 
-                is_in_bounds(bounds, neighbor_position).and_then(|neighbor_position| {
-                    let neighbor_elevation = elevations[neighbor_position.into_tuple()];
+    let mut image = Image::new(width, height);
 
-                    let can_traverse = elevation + 1 >= neighbor_elevation;
+    for ((x, y), &elevation) in elevations.indexed_iter() {
+        let brightness = ((elevation - b'a') as u32 * 255 / 25) as u8;
+
+        let color = match distances[(x, y)] {
+            Some(distance) => {
+                let closeness = 255 - (distance * 255 / max_distance) as u8;
+                Rgb::new(closeness, brightness, brightness / 2)
+            }
+            None => Rgb::new(brightness / 2, brightness / 2, brightness / 2),
+        };
 
-                    can_traverse.then(|| (neighbor_position, 1))
+        image.set(Vec2::new(x, y), color);
+    }
+
+    for &position in path {
+        image.set(position, Rgb::new(255, 255, 255));
+    }
+
+    image
+}
+
+/// Where the hiker can get to from the start, when the end is out of reach
+#[derive(Debug)]
+struct Unreachable {
+    reachable: usize,
+    highest_position: Vec2<usize>,
+    highest_elevation: u8,
+}
+
+impl Unreachable {
+    fn new(start: Vec2<usize>, elevations: &Elevations, rules: ClimbRules) -> Self {
+        let bounds = Vec2::from(elevations.dim());
+
+        let reachable = bfs_reach(start, |&position| {
+            let elevation = elevations[position.into_tuple()];
+
+            rules
+                .neighbors(bounds, position)
+                .filter(move |neighbor_position| {
+                    rules.can_climb(elevation, elevations[neighbor_position.into_tuple()])
                 })
-            })
-        },
-        |&position| position == end,
-    )?;
+        })
+        .collect_vec();
+
+        let highest_position = *reachable
+            .iter()
+            .max_by_key(|position| elevations[position.into_tuple()])
+            .unwrap();
+
+        Self {
+            reachable: reachable.len(),
+            highest_position,
+            highest_elevation: elevations[highest_position.into_tuple()],
+        }
+    }
+}
 
-    Some(steps)
+impl Display for Unreachable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "No path found, {} positions are reachable from the start, the highest being {:?} at {}",
+            self.reachable,
+            self.highest_elevation as char,
+            self.highest_position
+        )
+    }
 }
 
-fn solve_part1(input: &str) -> u32 {
+impl std::error::Error for Unreachable {}
+
+fn solve(input: &str) -> Result<(u32, u32)> {
     let ParseResult {
         elevations,
+        trail_starts,
         start,
         end,
-        ..
-    } = parse_input(input);
+    } = parse_input(input)?;
 
-    let elevations = elevations;
+    let rules = ClimbRules::default();
+    let distances = distances_to_end(end, &elevations, rules);
 
-    let steps = find_shortest_path(start, end, &elevations);
+    let part_1 =
+        distances[start.into_tuple()].ok_or_else(|| Unreachable::new(start, &elevations, rules))?;
 
-    steps.expect("No path found")
+    let part_2 = trail_starts
+        .iter()
+        .flat_map(|start| distances[start.into_tuple()])
+        .chain([part_1])
+        .min()
+        .unwrap();
+
+    Ok((part_1, part_2))
 }
 
-fn solve_part2(input: &str) -> u32 {
+pub fn main() -> Result<()> {
+    let input = include_str!("../input/day12.txt");
+
+    let result = execution_time(|| solve(input))?;
+    dbg!(result);
+
     let ParseResult {
         elevations,
-        trail_starts,
+        start,
         end,
         ..
-    } = parse_input(input);
+    } = parse_input(input)?;
 
-    let elevations = elevations;
+    let rules = ClimbRules::default();
 
-    let steps = trail_starts
-        .par_iter()
-        .flat_map(|&start| find_shortest_path(start, end, &elevations))
-        .min()
-        .unwrap();
+    let distances = distances_to_end(end, &elevations, rules);
+    let path = find_path(start, &elevations, &distances, rules)
+        .ok_or_else(|| Unreachable::new(start, &elevations, rules))?;
+
+    println!("{}", render_path(&path, elevations.dim()));
 
-    steps
+    if let Some(image_path) = env::args().nth(1) {
+        render_heat_map(&elevations, &distances, &path)
+            .save(image_path)
+            .context("Failed to save heat map")?;
+    }
+
+    Ok(())
 }
 
-pub fn main() {
-    let input = include_str!("../input/day12.txt");
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    execution_time(|| dbg!(solve_part1(input)));
+    const EXAMPLE: &str = "\
+Sabqponm
+abcryxxl
+accszExk
+acctuvwj
+abdefghi";
 
-    execution_time(|| dbg!(solve_part2(input)));
+    fn parse_error(input: &str) -> String {
+        parse_input(input).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn example() {
+        assert_eq!(solve(EXAMPLE).unwrap(), (31, 29));
+    }
+
+    #[test]
+    fn missing_start_or_end() {
+        assert_eq!(parse_error("abc\nabE"), "Missing start `S`");
+        assert_eq!(parse_error("Sbc\nabc"), "Missing end `E`");
+    }
+
+    #[test]
+    fn duplicate_start_or_end() {
+        assert_eq!(
+            parse_error("SbE\nabS"),
+            format!("Second start at {}", Vec2::new(2, 1))
+        );
+        assert_eq!(
+            parse_error("SbE\nEbc"),
+            format!("Second end at {}", Vec2::new(0, 1))
+        );
+    }
+
+    #[test]
+    fn ragged_rows() {
+        assert_eq!(parse_error("SbE\nab"), "Row 1 is 2 wide, expected 3");
+    }
+
+    #[test]
+    fn unknown_characters() {
+        assert_eq!(
+            parse_error("SbE\na1c"),
+            format!("Unknown character '1' at {}", Vec2::new(1, 1))
+        );
+        assert!(parse_input("").is_err());
+    }
+
+    #[test]
+    fn unreachable_end() {
+        let error = solve("SbcE\naaaa").unwrap_err();
+        let unreachable = error.downcast_ref::<Unreachable>().unwrap();
+
+        assert_eq!(unreachable.reachable, 7);
+        assert_eq!(unreachable.highest_elevation, b'c');
+        assert_eq!(unreachable.highest_position, Vec2::new(2, 0));
+    }
 }
@@ -1,9 +1,17 @@
 use advent_of_code::execution_time;
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
+use std::env;
+use std::fmt::{self, Display};
+use std::slice;
 
-#[derive(Debug, Clone)]
+/// Maps to JSON numbers and arrays, which is exactly the packet syntax
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
 pub enum Value {
-    Integer(u8),
+    Integer(i64),
     List(Vec<Value>),
 }
 
@@ -11,11 +19,13 @@ impl Ord for Value {
     fn cmp(&self, other: &Self) -> Ordering {
         use Value::*;
 
+        // An integer compared with a list acts like a list with one item, which is exactly
+        // a slice of just that integer
         match (self, other) {
             (Integer(a), Integer(b)) => a.cmp(b),
             (List(a), List(b)) => a.cmp(b),
-            (Integer(a), list) => List(vec![Integer(*a)]).cmp(list),
-            (list, Integer(b)) => list.cmp(&List(vec![Integer(*b)])),
+            (Integer(_), List(b)) => slice::from_ref(self).cmp(b),
+            (List(a), Integer(_)) => a.as_slice().cmp(slice::from_ref(other)),
         }
     }
 }
@@ -34,76 +44,568 @@ impl PartialOrd for Value {
     }
 }
 
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Integer(value) => write!(f, "{value}"),
+            Value::List(values) => {
+                write!(f, "[")?;
+
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+
+                    write!(f, "{value}")?;
+                }
+
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+/// Compares two packets like `Value::cmp`, and describes every step the same way the puzzle does
+pub fn explain_cmp(a: &Value, b: &Value) -> (Ordering, String) {
+    fn verdict(ordering: Ordering) -> &'static str {
+        match ordering {
+            Ordering::Less => "so inputs are in the right order",
+            _ => "so inputs are not in the right order",
+        }
+    }
+
+    fn explain(a: &Value, b: &Value, depth: usize, trace: &mut String) -> Ordering {
+        use Value::*;
+
+        let indent = "  ".repeat(depth);
+        trace.push_str(&format!("{indent}- Compare {a} vs {b}\n"));
+
+        let indent = "  ".repeat(depth + 1);
+
+        match (a, b) {
+            (Integer(x), Integer(y)) => {
+                let ordering = x.cmp(y);
+
+                let side = match ordering {
+                    Ordering::Less => "Left",
+                    Ordering::Greater => "Right",
+                    Ordering::Equal => return ordering,
+                };
+
+                trace.push_str(&format!(
+                    "{indent}- {side} side is smaller, {}\n",
+                    verdict(ordering)
+                ));
+
+                ordering
+            }
+            (List(xs), List(ys)) => {
+                for (x, y) in xs.iter().zip(ys) {
+                    let ordering = explain(x, y, depth + 1, trace);
+
+                    if ordering.is_ne() {
+                        return ordering;
+                    }
+                }
+
+                let ordering = xs.len().cmp(&ys.len());
+
+                let side = match ordering {
+                    Ordering::Less => "Left",
+                    Ordering::Greater => "Right",
+                    Ordering::Equal => return ordering,
+                };
+
+                trace.push_str(&format!(
+                    "{indent}- {side} side ran out of items, {}\n",
+                    verdict(ordering)
+                ));
+
+                ordering
+            }
+            (Integer(x), list) => {
+                let converted = List(vec![Integer(*x)]);
+                trace.push_str(&format!(
+                    "{indent}- Mixed types; convert left to {converted} and retry comparison\n"
+                ));
+
+                explain(&converted, list, depth + 1, trace)
+            }
+            (list, Integer(y)) => {
+                let converted = List(vec![Integer(*y)]);
+                trace.push_str(&format!(
+                    "{indent}- Mixed types; convert right to {converted} and retry comparison\n"
+                ));
+
+                explain(list, &converted, depth + 1, trace)
+            }
+        }
+    }
+
+    let mut trace = String::new();
+    let ordering = explain(a, b, 0, &mut trace);
+
+    (ordering, trace)
+}
+
+/// A syntax error, `pair` is counted from 1 and `offset` is in bytes from the start of the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub pair: usize,
+    pub offset: usize,
+    pub kind: nom::error::ErrorKind,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Syntax error in pair {} at byte {}: {}",
+            self.pair,
+            self.offset,
+            self.kind.description()
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 mod parser {
-    use crate::Value;
+    use crate::{ParseError, RawPacket, Value};
     use nom::branch::alt;
-    use nom::character::complete::{char, u8};
+    use nom::character::complete::{char, i64, multispace0};
+    use nom::combinator::{all_consuming, recognize};
     use nom::multi::separated_list0;
-    use nom::sequence::delimited;
-    use nom::Parser;
-    use nom::{Finish, IResult};
+    use nom::sequence::{delimited, pair, preceded, terminated};
+    use nom::{Finish, IResult, Offset, Parser};
+    use std::iter;
 
-    fn parse_value(input: &str) -> IResult<&str, Value> {
-        alt((
-            u8.map(Value::Integer),
-            delimited(
-                char('['),
-                separated_list0(char(','), parse_value),
-                char(']'),
-            )
-            .map(Value::List),
-        ))(input)
+    fn list(input: &str) -> IResult<&str, Vec<Value>> {
+        delimited(
+            terminated(char('['), multispace0),
+            separated_list0(delimited(multispace0, char(','), multispace0), value),
+            preceded(multispace0, char(']')),
+        )(input)
     }
 
-    pub fn parse(input: &str) -> impl Iterator<Item = (Value, Value)> + '_ {
-        let pairs = input.split("\n\n");
+    fn value(input: &str) -> IResult<&str, Value> {
+        alt((i64.map(Value::Integer), list.map(Value::List)))(input)
+    }
 
-        pairs.map(|pair| {
-            let (a, b) = pair.split_once("\n").unwrap();
+    fn packet_pair(input: &str) -> IResult<&str, (Value, Value)> {
+        let packet = || list.map(Value::List);
 
-            let parse = |input| parse_value(input).finish().unwrap().1;
+        pair(packet(), preceded(multispace0, packet()))(input)
+    }
 
-            let (a, b) = (parse(a), parse(b));
+    /// Checks the syntax of a list like `list`, without building any values
+    fn skip_list(input: &str) -> IResult<&str, ()> {
+        delimited(
+            terminated(char('['), multispace0),
+            separated_list0(delimited(multispace0, char(','), multispace0), skip_value),
+            preceded(multispace0, char(']')),
+        )
+        .map(|_| ())
+        .parse(input)
+    }
 
-            (a, b)
+    fn skip_value(input: &str) -> IResult<&str, ()> {
+        alt((i64.map(|_| ()), skip_list))(input)
+    }
+
+    /// Every non-empty line as a raw packet, after checking that it's valid
+    pub fn raw_packets(
+        input: &str,
+    ) -> impl Iterator<Item = Result<RawPacket<'_>, ParseError>> + '_ {
+        input
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .map(move |(index, line)| {
+                all_consuming(recognize(delimited(multispace0, skip_list, multispace0)))(line)
+                    .finish()
+                    .map(|(_, text)| RawPacket(text))
+                    .map_err(|error| ParseError {
+                        pair: index / 2 + 1,
+                        offset: input.offset(error.input),
+                        kind: error.code,
+                    })
+            })
+    }
+
+    pub fn parse(input: &str) -> impl Iterator<Item = Result<(Value, Value), ParseError>> + '_ {
+        let mut remaining = input;
+        let mut pair = 0;
+
+        iter::from_fn(move || {
+            remaining = remaining.trim_start();
+
+            if remaining.is_empty() {
+                return None;
+            }
+
+            pair += 1;
+
+            let result = match packet_pair(remaining).finish() {
+                Ok((rest, packets)) => {
+                    remaining = rest;
+                    Ok(packets)
+                }
+                Err(error) => {
+                    remaining = "";
+                    Err(ParseError {
+                        pair,
+                        offset: input.offset(error.input),
+                        kind: error.code,
+                    })
+                }
+            };
+
+            Some(result)
         })
     }
 }
 
-fn solve_part1(input: &str) -> usize {
-    let pairs = parser::parse(input);
+fn solve_part1(input: &str) -> Result<usize> {
+    let pairs: Vec<_> = parser::parse(input).try_collect()?;
 
-    pairs
+    let result = pairs
+        .iter()
         .enumerate()
         .filter(|(_, (a, b))| a < b)
         .map(|(index, _)| index + 1)
-        .sum()
+        .sum();
+
+    Ok(result)
 }
 
-fn solve_part2(input: &str) -> usize {
+fn solve_part2(input: &str) -> Result<usize> {
     use Value::*;
 
-    let pairs = parser::parse(input);
-    let packets = pairs.flat_map(|(a, b)| [a, b]);
-
     let divider_a = List(vec![List(vec![Integer(2)])]);
     let divider_b = List(vec![List(vec![Integer(6)])]);
 
-    let mut all_packets = vec![divider_a.clone(), divider_b.clone()];
-    all_packets.extend(packets);
+    // The position of a divider is one past the amount of packets sorted before it
+    let mut position_a = 1;
+    let mut position_b = 2;
+
+    for pair in parser::parse(input) {
+        let (a, b) = pair?;
+
+        for packet in [a, b] {
+            if packet < divider_a {
+                position_a += 1;
+            }
 
-    all_packets.sort_unstable();
+            if packet < divider_b {
+                position_b += 1;
+            }
+        }
+    }
 
-    let result = all_packets.partition_point(|v| v <= &divider_a)
-        * all_packets.partition_point(|v| v <= &divider_b);
+    Ok(position_a * position_b)
+}
+
+/// A packet compared straight from the input text, without parsing it into a `Value` first.
+/// Only built from text that has been checked to be a valid packet
+#[derive(Debug, Clone, Copy)]
+pub struct RawPacket<'a>(&'a str);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    Integer(i64),
+}
 
-    result
+/// The tokens of a raw packet. An integer can be wrapped in a list on the fly, which is done by
+/// emitting the opening bracket, the integer, and the closing brackets before continuing
+struct Tokens<'a> {
+    rest: &'a str,
+    wrapped_open: bool,
+    wrapped_integer: Option<i64>,
+    wrapped_closes: usize,
 }
 
-pub fn main() {
+impl<'a> Tokens<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            rest: text,
+            wrapped_open: false,
+            wrapped_integer: None,
+            wrapped_closes: 0,
+        }
+    }
+
+    fn text_token(&self) -> (Token, &'a str) {
+        let rest = self
+            .rest
+            .trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+
+        match rest.as_bytes()[0] {
+            b'[' => (Token::Open, &rest[1..]),
+            b']' => (Token::Close, &rest[1..]),
+            _ => {
+                let length = rest[1..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .map_or(rest.len(), |index| index + 1);
+
+                let integer = rest[..length].parse().expect("Invalid packet");
+
+                (Token::Integer(integer), &rest[length..])
+            }
+        }
+    }
+
+    fn peek(&self) -> Token {
+        if self.wrapped_open {
+            Token::Open
+        } else if let Some(integer) = self.wrapped_integer {
+            Token::Integer(integer)
+        } else if self.wrapped_closes > 0 {
+            Token::Close
+        } else {
+            self.text_token().0
+        }
+    }
+
+    fn advance(&mut self) {
+        if self.wrapped_open {
+            self.wrapped_open = false;
+        } else if self.wrapped_integer.is_some() {
+            self.wrapped_integer = None;
+        } else if self.wrapped_closes > 0 {
+            self.wrapped_closes -= 1;
+        } else {
+            self.rest = self.text_token().1;
+        }
+    }
+
+    /// Turns the current integer into a list holding only that integer
+    fn wrap(&mut self, integer: i64) {
+        if self.wrapped_integer.is_none() {
+            self.advance();
+        }
+
+        self.wrapped_open = true;
+        self.wrapped_integer = Some(integer);
+        self.wrapped_closes += 1;
+    }
+}
+
+impl Ord for RawPacket<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let mut a = Tokens::new(self.0);
+        let mut b = Tokens::new(other.0);
+
+        let mut depth = 0;
+
+        loop {
+            match (a.peek(), b.peek()) {
+                (Token::Open, Token::Open) => depth += 1,
+                (Token::Close, Token::Close) => {
+                    depth -= 1;
+
+                    if depth == 0 {
+                        return Ordering::Equal;
+                    }
+                }
+                (Token::Close, _) => return Ordering::Less,
+                (_, Token::Close) => return Ordering::Greater,
+                (Token::Integer(x), Token::Integer(y)) => {
+                    if x != y {
+                        return x.cmp(&y);
+                    }
+                }
+                (Token::Integer(x), Token::Open) => {
+                    a.wrap(x);
+                    continue;
+                }
+                (Token::Open, Token::Integer(y)) => {
+                    b.wrap(y);
+                    continue;
+                }
+            }
+
+            a.advance();
+            b.advance();
+        }
+    }
+}
+
+impl PartialEq for RawPacket<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(self.cmp(other), Ordering::Equal)
+    }
+}
+
+impl Eq for RawPacket<'_> {}
+
+impl PartialOrd for RawPacket<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Same as part 2, but compares the packets in place, for inputs too large to parse up front
+fn solve_part2_raw(input: &str) -> Result<usize> {
+    let divider_a = RawPacket("[[2]]");
+    let divider_b = RawPacket("[[6]]");
+
+    let mut position_a = 1;
+    let mut position_b = 2;
+
+    for packet in parser::raw_packets(input) {
+        let packet = packet?;
+
+        if packet < divider_a {
+            position_a += 1;
+        }
+
+        if packet < divider_b {
+            position_b += 1;
+        }
+    }
+
+    Ok(position_a * position_b)
+}
+
+pub fn main() -> Result<()> {
     let input = include_str!("../input/day13.txt");
 
-    execution_time(|| dbg!(solve_part1(input)));
+    let part_1 = execution_time(|| solve_part1(input))?;
+    dbg!(part_1);
+
+    let part_2 = execution_time(|| solve_part2(input))?;
+    dbg!(part_2);
+
+    let part_2_raw = execution_time(|| solve_part2_raw(input))?;
+    dbg!(part_2_raw);
+
+    if let Some(pair) = env::args().nth(1) {
+        let pair: usize = pair.parse().context("Pair index should be a number")?;
+
+        let (a, b) = parser::parse(input)
+            .nth(pair.checked_sub(1).context("Pairs are counted from 1")?)
+            .context("No such pair")??;
+
+        let (_, trace) = explain_cmp(&a, &b);
+        print!("== Pair {pair} ==\n{trace}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn value() -> impl Strategy<Value = Value> {
+        let integer = any::<i64>().prop_map(Value::Integer);
+
+        integer.prop_recursive(4, 64, 6, |inner| {
+            prop::collection::vec(inner, 0..6).prop_map(Value::List)
+        })
+    }
+
+    fn packet() -> impl Strategy<Value = Value> {
+        prop::collection::vec(value(), 0..6).prop_map(Value::List)
+    }
+
+    const EXAMPLE: &str = "\
+[1,1,3,1,1]
+[1,1,5,1,1]
+
+[[1],[2,3,4]]
+[[1],4]
+
+[9]
+[[8,7,6]]
+
+[[4,4],4,4]
+[[4,4],4,4,4]
+
+[7,7,7,7]
+[7,7,7]
+
+[]
+[3]
+
+[[[]]]
+[[]]
+
+[1,[2,[3,[4,[5,6,7]]]],8,9]
+[1,[2,[3,[4,[5,6,0]]]],8,9]
+";
+
+    #[test]
+    fn example() {
+        assert_eq!(solve_part1(EXAMPLE).unwrap(), 13);
+        assert_eq!(solve_part2(EXAMPLE).unwrap(), 140);
+        assert_eq!(solve_part2_raw(EXAMPLE).unwrap(), 140);
+    }
+
+    #[test]
+    fn raw_packets_compare_like_values() {
+        let divider_text = "[[2]]\n[[6]]\n";
+        let text = format!("{EXAMPLE}{divider_text}");
 
-    execution_time(|| dbg!(solve_part2(input)));
+        let raw_packets: Vec<_> = parser::raw_packets(&text).try_collect().unwrap();
+        let values = parser::parse(&text)
+            .map(|pair| pair.unwrap())
+            .flat_map(|(a, b)| [a, b])
+            .collect_vec();
+
+        assert_eq!(raw_packets.len(), values.len());
+
+        for (raw_a, a) in raw_packets.iter().zip(&values) {
+            for (raw_b, b) in raw_packets.iter().zip(&values) {
+                assert_eq!(raw_a.cmp(raw_b), a.cmp(b), "{a} vs {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_raw_packets() {
+        for text in ["[1,2", "[1,,2]", "1", "[1]]", "[a]", "[1,[2]\n[3]"] {
+            assert!(
+                parser::raw_packets(text).any(|packet| packet.is_err()),
+                "{text:?}"
+            );
+        }
+
+        let error = solve_part2_raw("[1]\n[2]\n\n[3]\n[4,]\n").unwrap_err();
+        let error = error.downcast_ref::<ParseError>().unwrap();
+
+        assert_eq!((error.pair, error.offset), (2, 15));
+    }
+
+    proptest! {
+        // `Value: PartialEq` treats `2` and `[2]` as equal, so packets are compared as text
+        #[test]
+        fn display_round_trip(a in packet(), b in packet()) {
+            let text = format!("{a}\n{b}\n");
+            let pairs: Vec<_> = parser::parse(&text).try_collect().unwrap();
+
+            prop_assert_eq!(pairs.len(), 1);
+            prop_assert_eq!(pairs[0].0.to_string(), a.to_string());
+            prop_assert_eq!(pairs[0].1.to_string(), b.to_string());
+        }
+
+        #[test]
+        fn raw_packets_match_values(a in packet(), b in packet()) {
+            let (a_text, b_text) = (a.to_string(), b.to_string());
+
+            prop_assert_eq!(RawPacket(&a_text).cmp(&RawPacket(&b_text)), a.cmp(&b));
+        }
+
+        #[test]
+        fn display_matches_json(value in value()) {
+            prop_assert_eq!(serde_json::to_string(&value).unwrap(), value.to_string());
+
+            let parsed: Value = serde_json::from_str(&value.to_string()).unwrap();
+            prop_assert_eq!(parsed.to_string(), value.to_string());
+        }
+    }
 }
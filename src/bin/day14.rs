@@ -1,137 +1,710 @@
 use advent_of_code::execution_time;
-use extent::Extent;
+use advent_of_code::ppm::Image;
+use anyhow::{ensure, Context, Result};
 use hashbrown::HashSet;
-use itertools::{Either, Itertools};
-use vek::Vec2;
+use itertools::Itertools;
+use ndarray::Array2;
+use std::fmt::{self, Display};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::{env, fs, iter, mem};
+use vek::{Rgb, Vec2};
 
 struct ParseResult {
     tiles: HashSet<Vec2<i32>>,
     max_y: i32,
 }
 
-fn parse_input(input: &str) -> ParseResult {
-    let stone_paths = input.split("\n").map(|path| {
-        path.split(" -> ").map(|point| {
-            let (x, y) = point.split_once(',').unwrap();
-            let (x, y) = (x.parse::<i32>().unwrap(), y.parse::<i32>().unwrap());
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RockPathError {
+    InvalidPoint {
+        line: usize,
+        point: String,
+    },
+    ZeroLengthSegment {
+        line: usize,
+        point: Vec2<i32>,
+    },
+    DuplicatePoint {
+        line: usize,
+        point: Vec2<i32>,
+    },
+    DiagonalSegment {
+        line: usize,
+        from: Vec2<i32>,
+        to: Vec2<i32>,
+    },
+    NoRocks,
+}
 
-            Vec2::<i32>::new(x, y)
-        })
-    });
+impl Display for RockPathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RockPathError::InvalidPoint { line, point } => {
+                write!(f, "Line {line}: {point:?} is not a point")
+            }
+            RockPathError::ZeroLengthSegment { line, point } => {
+                write!(f, "Line {line}: {point} is repeated right after itself")
+            }
+            RockPathError::DuplicatePoint { line, point } => {
+                write!(f, "Line {line}: {point} appears more than once")
+            }
+            RockPathError::DiagonalSegment { line, from, to } => {
+                write!(
+                    f,
+                    "Line {line}: the segment from {from} to {to} is diagonal"
+                )
+            }
+            RockPathError::NoRocks => write!(f, "There are no rock paths"),
+        }
+    }
+}
 
-    let max_y = stone_paths
-        .clone()
-        .flatten()
-        .map(|point| point.y)
-        .max()
-        .unwrap();
+impl std::error::Error for RockPathError {}
 
-    let stone_points = stone_paths.flat_map(|stone_path| {
-        stone_path.tuple_windows::<(_, _)>().flat_map(|(a, b)| {
-            let difference = a - b;
+/// What to do with segments that aren't horizontal or vertical
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiagonalSegments {
+    Reject,
+    Rasterize,
+}
 
-            let positions_between = match difference {
-                Vec2 { x: 0, y: _y } => {
-                    Either::Left(Extent::new(a.y, b.y).iter().map(move |y| a.with_y(y)))
-                }
-                Vec2 { x: _x, y: 0 } => {
-                    Either::Right(Extent::new(a.x, b.x).iter().map(move |x| a.with_x(x)))
-                }
-                _ => unreachable!(),
-            };
+/// Every position on the line from `a` to `b`, including both ends
+fn bresenham(a: Vec2<i32>, b: Vec2<i32>) -> impl Iterator<Item = Vec2<i32>> {
+    let delta = Vec2::new((b.x - a.x).abs(), -(b.y - a.y).abs());
+    let step = Vec2::new((b.x - a.x).signum(), (b.y - a.y).signum());
+
+    let mut position = Some(a);
+    let mut error = delta.x + delta.y;
+
+    iter::from_fn(move || {
+        let current = position?;
 
-            positions_between
-        })
-    });
+        position = (current != b).then(|| {
+            let mut next = current;
+            let doubled_error = 2 * error;
 
-    let tiles = HashSet::from_iter(stone_points);
+            if doubled_error >= delta.y {
+                error += delta.y;
+                next.x += step.x;
+            }
+
+            if doubled_error <= delta.x {
+                error += delta.x;
+                next.y += step.y;
+            }
 
-    ParseResult { tiles, max_y }
+            next
+        });
+
+        Some(current)
+    })
 }
 
-fn solve_part1(input: &str) -> i32 {
-    let ParseResult { max_y, mut tiles } = parse_input(input);
+fn parse_point(line: usize, point: &str) -> Result<Vec2<i32>, RockPathError> {
+    let parse = || {
+        let (x, y) = point.split_once(',')?;
+        let (x, y) = (x.trim().parse().ok()?, y.trim().parse().ok()?);
 
-    let sand_spawn = Vec2::new(500, 0);
+        Some(Vec2::new(x, y))
+    };
 
-    let mut sand_in_rest = 0;
+    parse().ok_or_else(|| RockPathError::InvalidPoint {
+        line,
+        point: point.to_owned(),
+    })
+}
 
-    loop {
-        let mut sand_position = sand_spawn;
+fn parse_input(input: &str, diagonals: DiagonalSegments) -> Result<ParseResult, RockPathError> {
+    let mut tiles = HashSet::new();
+    let mut max_y = i32::MIN;
 
-        loop {
-            let mut try_move = |offset: Vec2<i32>| {
-                if !tiles.contains(&(sand_position + offset)) {
-                    sand_position += offset;
-                    true
-                } else {
-                    false
-                }
-            };
+    for (index, path) in input.lines().enumerate() {
+        let line = index + 1;
 
-            const DOWN: Vec2<i32> = Vec2::new(0, 1);
-            const DOWN_LEFT: Vec2<i32> = Vec2::new(-1, 1);
-            const DOWN_RIGHT: Vec2<i32> = Vec2::new(1, 1);
+        if path.trim().is_empty() {
+            continue;
+        }
+
+        let points: Vec<_> = path
+            .split("->")
+            .map(|point| parse_point(line, point.trim()))
+            .try_collect()?;
+
+        for (a, b) in points.iter().tuple_windows() {
+            if a == b {
+                return Err(RockPathError::ZeroLengthSegment { line, point: *a });
+            }
 
-            let did_move = try_move(DOWN) || try_move(DOWN_LEFT) || try_move(DOWN_RIGHT);
+            let is_diagonal = a.x != b.x && a.y != b.y;
 
-            if !did_move {
-                tiles.insert(sand_position);
-                sand_in_rest += 1;
+            if is_diagonal && diagonals == DiagonalSegments::Reject {
+                return Err(RockPathError::DiagonalSegment {
+                    line,
+                    from: *a,
+                    to: *b,
+                });
+            }
+        }
 
-                break;
-            } else if sand_position.y > max_y {
-                return sand_in_rest;
+        if let Some(point) = points.iter().duplicates().next() {
+            return Err(RockPathError::DuplicatePoint {
+                line,
+                point: *point,
+            });
+        }
+
+        max_y = points.iter().map(|point| point.y).fold(max_y, i32::max);
+
+        match points.as_slice() {
+            [point] => {
+                tiles.insert(*point);
+            }
+            _ => {
+                for (&a, &b) in points.iter().tuple_windows() {
+                    tiles.extend(bresenham(a, b));
+                }
             }
         }
     }
+
+    if tiles.is_empty() {
+        return Err(RockPathError::NoRocks);
+    }
+
+    Ok(ParseResult { tiles, max_y })
 }
 
-fn solve_part2(input: &str) -> i32 {
-    let ParseResult { max_y, mut tiles } = parse_input(input);
-    let floor = max_y + 1;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Tile {
+    #[default]
+    Air,
+    Rock,
+    Sand,
+}
 
-    let sand_spawn = Vec2::new(500, 0);
+const DOWN: Vec2<i32> = Vec2::new(0, 1);
+const DOWN_LEFT: Vec2<i32> = Vec2::new(-1, 1);
+const DOWN_RIGHT: Vec2<i32> = Vec2::new(1, 1);
 
-    let mut sand_in_rest = 0;
+/// Where sand comes from, what's below the rocks, and how sand moves
+#[derive(Debug, Clone)]
+struct CaveConfig {
+    /// Sand is dropped from each source in turn
+    sources: Vec<Vec2<i32>>,
+    /// How far below the lowest rock an infinite floor lies. Without a floor, sand falling past
+    /// the lowest rock is lost to the abyss
+    floor_offset: Option<i32>,
+    /// The x coordinates of walls reaching from the top of the cave to the bottom
+    walls: Vec<i32>,
+    /// The moves a grain tries in order, every move has to go down or sideways. A grain never
+    /// moves sideways back onto its own path, so it always comes to rest. Sand spreading
+    /// sideways further than falling would take it is lost to the abyss, unless walls stop it
+    flow: Vec<Vec2<i32>>,
+}
 
-    loop {
-        let mut sand_position = sand_spawn;
+impl CaveConfig {
+    /// The cave from part 1
+    fn abyss() -> Self {
+        Self {
+            sources: vec![Vec2::new(500, 0)],
+            floor_offset: None,
+            walls: Vec::new(),
+            flow: vec![DOWN, DOWN_LEFT, DOWN_RIGHT],
+        }
+    }
+
+    /// The cave from part 2
+    fn floor() -> Self {
+        Self {
+            floor_offset: Some(2),
+            ..Self::abyss()
+        }
+    }
+}
+
+/// What happened to a dropped grain of sand
+enum Drop {
+    Rest(Vec2<i32>),
+    Abyss,
+    /// The sand has piled up to the source
+    Blocked,
+}
+
+struct Cave {
+    /// Indexed by `(x, y)` relative to `origin`
+    tiles: Array2<Tile>,
+    origin: Vec2<i32>,
+    floor_y: Option<i32>,
+    sources: Vec<Vec2<i32>>,
+    flow: Vec<Vec2<i32>>,
+    /// The falling path of the previous grain from each source
+    paths: Vec<Vec<Vec2<i32>>>,
+    next_source: usize,
+}
+
+impl Cave {
+    fn new(rocks: &HashSet<Vec2<i32>>, max_y: i32, config: CaveConfig) -> Result<Self> {
+        let CaveConfig {
+            sources,
+            floor_offset,
+            walls,
+            flow,
+        } = config;
+
+        ensure!(!sources.is_empty(), "The cave needs a source of sand");
+        ensure!(
+            flow.iter()
+                .all(|offset| offset.y > 0 || (offset.y == 0 && offset.x != 0)),
+            "Sand can only flow downwards or sideways"
+        );
+        ensure!(
+            floor_offset.is_none_or(|floor_offset| floor_offset >= 1),
+            "The floor has to lie below the lowest rock"
+        );
+
+        // Without a floor, the row below the lowest rock or source is the last one before the
+        // abyss
+        let bottom_y = match floor_offset {
+            Some(floor_offset) => max_y + floor_offset,
+            None => sources.iter().map(|source| source.y).fold(max_y, i32::max) + 1,
+        };
+        let min_y = sources
+            .iter()
+            .chain(rocks)
+            .map(|position| position.y)
+            .min()
+            .unwrap()
+            .min(0);
+
+        // Sand can't spread sideways any faster than the flow allows while falling
+        let max_spread = flow.iter().map(|offset| offset.x.abs()).max().unwrap_or(0);
+
+        let source_xs = sources.iter().flat_map(|source| {
+            let spread = (bottom_y - source.y) * max_spread;
+            [source.x - spread, source.x + spread]
+        });
+
+        let all_xs = rocks
+            .iter()
+            .map(|rock| rock.x)
+            .chain(source_xs)
+            .chain(walls.iter().copied());
+
+        let (min_x, max_x) = all_xs.minmax().into_option().unwrap();
+
+        let origin = Vec2::new(min_x - 1, min_y);
+        let size = Vec2::new(max_x - min_x + 3, bottom_y - min_y + 1).as_::<usize>();
+
+        let mut tiles = Array2::default(size.into_tuple());
+
+        for &rock in rocks {
+            tiles[(rock - origin).as_::<usize>().into_tuple()] = Tile::Rock;
+        }
+
+        for wall_x in walls {
+            tiles.row_mut((wall_x - origin.x) as usize).fill(Tile::Rock);
+        }
+
+        let floor_y = floor_offset.map(|_| bottom_y);
+
+        if let Some(floor_y) = floor_y {
+            tiles
+                .column_mut((floor_y - origin.y) as usize)
+                .fill(Tile::Rock);
+        }
+
+        let cave = Self {
+            tiles,
+            origin,
+            floor_y,
+            paths: sources.iter().map(|&source| vec![source]).collect(),
+            sources,
+            flow,
+            next_source: 0,
+        };
+
+        for &source in &cave.sources {
+            ensure!(
+                cave.tile(source) == Some(Tile::Air),
+                "The source at {source} is outside of the cave or inside rock"
+            );
+        }
+
+        Ok(cave)
+    }
+
+    /// `None` if the position is outside of the cave, which means it's in the abyss
+    fn tile(&self, position: Vec2<i32>) -> Option<Tile> {
+        let index = position - self.origin;
+
+        if index.x < 0 || index.y < 0 {
+            return None;
+        }
+
+        self.tiles.get(index.as_::<usize>().into_tuple()).copied()
+    }
+
+    /// Drops a grain of sand from the next source that isn't blocked, returning where it comes
+    /// to rest. Returns `None` if it falls into the abyss, or if every source is blocked
+    fn drop_grain(&mut self) -> Option<Vec2<i32>> {
+        for _ in 0..self.sources.len() {
+            let source = self.next_source;
+            self.next_source = (self.next_source + 1) % self.sources.len();
+
+            let mut path = mem::take(&mut self.paths[source]);
+            let drop = self.follow_path(&mut path);
+            self.paths[source] = path;
+
+            match drop {
+                Drop::Rest(position) => return Some(position),
+                Drop::Abyss => return None,
+                Drop::Blocked => {}
+            }
+        }
+
+        None
+    }
+
+    /// Every grain follows the previous grain's path up until the previous grain's resting
+    /// place, so the grain starts falling from the last free position of that path
+    fn follow_path(&mut self, path: &mut Vec<Vec2<i32>>) -> Drop {
+        // Sand from other sources might have come to rest on the path since
+        if self.sources.len() > 1 {
+            let free_length = path
+                .iter()
+                .take_while(|&&position| self.tile(position) == Some(Tile::Air))
+                .count();
+
+            path.truncate(free_length);
+        }
 
         loop {
-            let mut try_move = |offset: Vec2<i32>| {
-                if !tiles.contains(&(sand_position + offset)) {
-                    sand_position += offset;
-                    true
-                } else {
-                    false
-                }
+            let Some(&sand_position) = path.last() else {
+                return Drop::Blocked;
             };
 
-            const DOWN: Vec2<i32> = Vec2::new(0, 1);
-            const DOWN_LEFT: Vec2<i32> = Vec2::new(-1, 1);
-            const DOWN_RIGHT: Vec2<i32> = Vec2::new(1, 1);
+            let mut next_position = None;
 
-            let did_move = try_move(DOWN) || try_move(DOWN_LEFT) || try_move(DOWN_RIGHT);
+            for &offset in &self.flow {
+                let position = sand_position + offset;
 
-            if !did_move || sand_position.y == floor {
-                tiles.insert(sand_position);
-                sand_in_rest += 1;
+                // Flowing left and right could go back and forth forever
+                if offset.y == 0 && path.contains(&position) {
+                    continue;
+                }
 
-                if sand_position == sand_spawn {
-                    return sand_in_rest;
+                match self.tile(position) {
+                    None => return Drop::Abyss,
+                    Some(Tile::Air) => {
+                        next_position = Some(position);
+                        break;
+                    }
+                    Some(_) => {}
                 }
+            }
 
-                break;
+            match next_position {
+                Some(next_position) => path.push(next_position),
+                None => {
+                    path.pop();
+                    self.tiles[(sand_position - self.origin).as_::<usize>().into_tuple()] =
+                        Tile::Sand;
+
+                    return Drop::Rest(sand_position);
+                }
             }
         }
     }
 }
 
-pub fn main() {
+impl Cave {
+    /// The area covering the sources and every rock and grain of sand, not counting the floor
+    fn bounding_box(&self) -> (Vec2<i32>, Vec2<i32>) {
+        let occupied = self
+            .tiles
+            .indexed_iter()
+            .filter(|&(_, &tile)| tile != Tile::Air)
+            .map(|((x, y), _)| Vec2::new(x, y).as_::<i32>() + self.origin)
+            .filter(|position| Some(position.y) != self.floor_y);
+
+        let source = self.sources[0];
+
+        occupied.chain(self.sources.iter().copied()).fold(
+            (source, source),
+            |(min, max), position| {
+                (
+                    Vec2::partial_min(min, position),
+                    Vec2::partial_max(max, position),
+                )
+            },
+        )
+    }
+
+    /// Draws the cave like in the puzzle description
+    fn render(&self) -> String {
+        let (min, max) = self.bounding_box();
+        let max_y = self.floor_y.unwrap_or(max.y);
+
+        (min.y..=max_y)
+            .map(|y| {
+                (min.x..=max.x)
+                    .map(|x| {
+                        let position = Vec2::new(x, y);
+
+                        match self.tile(position) {
+                            _ if self.sources.contains(&position) => '+',
+                            Some(Tile::Rock) => '#',
+                            Some(Tile::Sand) => 'o',
+                            _ => '.',
+                        }
+                    })
+                    .collect::<String>()
+            })
+            .join("\n")
+    }
+
+    fn to_image(&self) -> Image {
+        let (width, height) = self.tiles.dim();
+        let mut image = Image::new(width, height);
+
+        for ((x, y), &tile) in self.tiles.indexed_iter() {
+            let color = match tile {
+                Tile::Air => Rgb::new(16, 16, 32),
+                Tile::Rock => Rgb::new(128, 128, 128),
+                Tile::Sand => Rgb::new(230, 190, 100),
+            };
+
+            image.set(Vec2::new(x, y), color);
+        }
+
+        for &source in &self.sources {
+            image.set((source - self.origin).as_(), Rgb::new(255, 0, 0));
+        }
+
+        image
+    }
+}
+
+/// Saves an image of the cave to `directory` after every `interval` grains of sand, and once
+/// all sand has come to rest
+fn save_frames(
+    rocks: &ParseResult,
+    config: CaveConfig,
+    directory: &Path,
+    interval: NonZeroUsize,
+) -> Result<()> {
+    let mut cave = Cave::new(&rocks.tiles, rocks.max_y, config)?;
+
+    fs::create_dir_all(directory)?;
+
+    let mut frame = 0;
+    let mut save_frame = |cave: &Cave| {
+        let path = directory.join(format!("frame_{frame:05}.ppm"));
+        frame += 1;
+
+        cave.to_image().save(path)
+    };
+
+    for grains in 1.. {
+        if cave.drop_grain().is_none() {
+            break;
+        }
+
+        if grains % interval.get() == 0 {
+            save_frame(&cave)?;
+        }
+    }
+
+    save_frame(&cave)?;
+
+    Ok(())
+}
+
+fn count_sand_in_rest(
+    input: &str,
+    diagonals: DiagonalSegments,
+    config: CaveConfig,
+) -> Result<usize> {
+    let ParseResult { tiles, max_y } = parse_input(input, diagonals)?;
+
+    let mut cave = Cave::new(&tiles, max_y, config)?;
+
+    Ok(iter::from_fn(|| cave.drop_grain()).count())
+}
+
+fn solve_part1(input: &str, diagonals: DiagonalSegments) -> Result<usize> {
+    count_sand_in_rest(input, diagonals, CaveConfig::abyss())
+}
+
+fn solve_part2(input: &str, diagonals: DiagonalSegments) -> Result<usize> {
+    count_sand_in_rest(input, diagonals, CaveConfig::floor())
+}
+
+pub fn main() -> Result<()> {
     let input = include_str!("../input/day14.txt");
 
-    execution_time(|| dbg!(solve_part1(input)));
+    let (flags, args): (Vec<_>, Vec<_>) =
+        env::args().skip(1).partition(|arg| arg.starts_with("--"));
+
+    let diagonals = if flags.iter().any(|flag| flag == "--diagonals") {
+        DiagonalSegments::Rasterize
+    } else {
+        DiagonalSegments::Reject
+    };
+
+    let part_1 = execution_time(|| solve_part1(input, diagonals))?;
+    dbg!(part_1);
+
+    let part_2 = execution_time(|| solve_part2(input, diagonals))?;
+    dbg!(part_2);
+
+    let rocks = parse_input(input, diagonals)?;
 
-    execution_time(|| dbg!(solve_part2(input)));
+    let mut args = args.into_iter();
+
+    if let Some(directory) = args.next() {
+        let interval = match args.next() {
+            Some(interval) => interval
+                .parse()
+                .context("The frame interval should be a positive number")?,
+            None => NonZeroUsize::new(100).unwrap(),
+        };
+
+        save_frames(&rocks, CaveConfig::floor(), Path::new(&directory), interval)?;
+    } else {
+        let mut cave = Cave::new(&rocks.tiles, rocks.max_y, CaveConfig::abyss())?;
+        while cave.drop_grain().is_some() {}
+
+        println!("{}", cave.render());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+498,4 -> 498,6 -> 496,6
+503,4 -> 502,4 -> 502,9 -> 494,9";
+
+    fn cave(input: &str, config: CaveConfig) -> Result<Cave> {
+        let rocks = parse_input(input, DiagonalSegments::Reject)?;
+
+        Cave::new(&rocks.tiles, rocks.max_y, config)
+    }
+
+    #[test]
+    fn example() {
+        let diagonals = DiagonalSegments::Reject;
+
+        assert_eq!(solve_part1(EXAMPLE, diagonals).unwrap(), 24);
+        assert_eq!(solve_part2(EXAMPLE, diagonals).unwrap(), 93);
+    }
+
+    #[test]
+    fn no_rocks() {
+        for input in ["", "\n  \n"] {
+            assert_eq!(
+                parse_input(input, DiagonalSegments::Reject).err(),
+                Some(RockPathError::NoRocks)
+            );
+
+            assert!(solve_part1(input, DiagonalSegments::Reject).is_err());
+        }
+    }
+
+    #[test]
+    fn rocks_above_the_source() {
+        let input = "500,-3 -> 502,-3";
+
+        assert_eq!(solve_part1(input, DiagonalSegments::Reject).unwrap(), 0);
+        assert!(cave(input, CaveConfig::floor()).is_err());
+    }
+
+    #[test]
+    fn source_below_the_rocks() {
+        let config = CaveConfig {
+            sources: vec![Vec2::new(500, 20)],
+            ..CaveConfig::abyss()
+        };
+
+        let mut cave = cave(EXAMPLE, config).unwrap();
+
+        assert_eq!(cave.drop_grain(), None);
+    }
+
+    #[test]
+    fn sources_outside_of_the_cave() {
+        let below_the_floor = CaveConfig {
+            sources: vec![Vec2::new(500, 20)],
+            ..CaveConfig::floor()
+        };
+        let inside_rock = CaveConfig {
+            sources: vec![Vec2::new(500, 9)],
+            ..CaveConfig::abyss()
+        };
+        let inside_a_wall = CaveConfig {
+            walls: vec![500],
+            ..CaveConfig::abyss()
+        };
+
+        assert!(cave(EXAMPLE, below_the_floor).is_err());
+        assert!(cave(EXAMPLE, inside_rock).is_err());
+        assert!(cave(EXAMPLE, inside_a_wall).is_err());
+    }
+
+    #[test]
+    fn floor_above_the_rocks() {
+        for floor_offset in [0, -1, -20] {
+            let config = CaveConfig {
+                floor_offset: Some(floor_offset),
+                ..CaveConfig::floor()
+            };
+
+            assert!(cave(EXAMPLE, config).is_err());
+        }
+    }
+
+    #[test]
+    fn sideways_flow() {
+        const LEFT: Vec2<i32> = Vec2::new(-1, 0);
+        const RIGHT: Vec2<i32> = Vec2::new(1, 0);
+
+        // Fills the cup row by row like water, then spills over the left edge
+        let cup = "494,2 -> 494,6 -> 506,6 -> 506,2";
+        let config = CaveConfig {
+            flow: vec![DOWN, LEFT, RIGHT],
+            ..CaveConfig::abyss()
+        };
+
+        let mut cave = cave(cup, config).unwrap();
+
+        assert_eq!(cave.drop_grain(), Some(Vec2::new(495, 5)));
+        assert_eq!(cave.drop_grain(), Some(Vec2::new(496, 5)));
+        assert_eq!(iter::from_fn(|| cave.drop_grain()).count(), 11 * 4 - 2);
+
+        // Without anything to stop it, sand flowing sideways ends up in the abyss
+        let config = CaveConfig {
+            flow: vec![LEFT, RIGHT, DOWN],
+            ..CaveConfig::floor()
+        };
+
+        assert_eq!(
+            count_sand_in_rest(EXAMPLE, DiagonalSegments::Reject, config).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn upwards_flow() {
+        let config = CaveConfig {
+            flow: vec![DOWN, Vec2::new(0, -1)],
+            ..CaveConfig::abyss()
+        };
+
+        assert!(cave(EXAMPLE, config).is_err());
+    }
 }
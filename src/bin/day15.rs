@@ -1,8 +1,13 @@
 use advent_of_code::execution_time;
-use anyhow::Context;
+use advent_of_code::iterator_helpers::IteratorHelpers;
+use advent_of_code::ppm::Image;
+use anyhow::{ensure, Context, Result};
 use itertools::{Either, Itertools};
 use rayon::prelude::*;
-use vek::Vec2;
+use std::env;
+use std::fmt::{self, Display};
+use std::slice;
+use vek::{Rgb, Vec2};
 
 fn manhattan_distance(a: Vec2<i32>, b: Vec2<i32>) -> u32 {
     i32::abs_diff(a.x, b.x) + i32::abs_diff(a.y, b.y)
@@ -24,28 +29,103 @@ impl Measurement {
     }
 }
 
-fn parse_input(input: &str) -> impl Iterator<Item = Measurement> + '_ {
-    input.lines().map(|line| {
-        let mut parts = line.split(['=', ',', ':']);
+/// A syntax error, `line` and `column` are counted from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: nom::error::ErrorKind,
+}
 
-        fn parse(number: Option<&str>) -> anyhow::Result<i32> {
-            let number = number.context("Parsing error")?;
-            let number = number.parse()?;
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Syntax error on line {} at column {}: {}",
+            self.line,
+            self.column,
+            self.kind.description()
+        )
+    }
+}
 
-            Ok(number)
+impl std::error::Error for ParseError {}
+
+mod parser {
+    use crate::{Measurement, ParseError};
+    use nom::bytes::complete::tag;
+    use nom::character::complete::{char, i32, space0, space1};
+    use nom::combinator::all_consuming;
+    use nom::sequence::{delimited, preceded, separated_pair, tuple};
+    use nom::{Finish, IResult, Offset, Parser};
+    use vek::Vec2;
+
+    /// Words separated by any amount of spaces
+    fn phrase<'a>(words: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, ()> {
+        move |mut input| {
+            for (index, word) in words.split(' ').enumerate() {
+                if index > 0 {
+                    (input, _) = space1(input)?;
+                }
+
+                (input, _) = tag(word)(input)?;
+            }
+
+            Ok((input, ()))
         }
+    }
+
+    fn coordinate<'a>(name: char) -> impl FnMut(&'a str) -> IResult<&'a str, i32> {
+        preceded(tuple((char(name), space0, char('='), space0)), i32)
+    }
 
-        let sensor_x = parse(parts.nth(1)).unwrap();
-        let sensor_y = parse(parts.nth(1)).unwrap();
-        let beacon_x = parse(parts.nth(1)).unwrap();
-        let beacon_y = parse(parts.nth(1)).unwrap();
+    fn position(input: &str) -> IResult<&str, Vec2<i32>> {
+        separated_pair(
+            coordinate('x'),
+            delimited(space0, char(','), space0),
+            coordinate('y'),
+        )
+        .map(|(x, y)| Vec2::new(x, y))
+        .parse(input)
+    }
 
-        Measurement::new(Vec2::new(sensor_x, sensor_y), Vec2::new(beacon_x, beacon_y))
-    })
+    fn measurement(input: &str) -> IResult<&str, Measurement> {
+        let sensor = preceded(tuple((phrase("Sensor at"), space1)), position);
+        let beacon = preceded(tuple((phrase("closest beacon is at"), space1)), position);
+
+        all_consuming(delimited(
+            space0,
+            separated_pair(sensor, delimited(space0, char(':'), space0), beacon),
+            space0,
+        ))
+        .map(|(sensor_position, beacon_position)| {
+            Measurement::new(sensor_position, beacon_position)
+        })
+        .parse(input)
+    }
+
+    /// Parses one measurement per line, skipping blank lines
+    pub fn parse(input: &str) -> impl Iterator<Item = Result<Measurement, ParseError>> + '_ {
+        input
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                measurement(line)
+                    .finish()
+                    .map(|(_, measurement)| measurement)
+                    .map_err(|error| ParseError {
+                        line: index + 1,
+                        column: line.offset(error.input) + 1,
+                        kind: error.code,
+                    })
+            })
+    }
 }
 
-fn solve_part1(input: &str) -> usize {
-    let measurements = parse_input(input).collect_vec();
+fn solve_part1(input: &str) -> Result<usize> {
+    let measurements: Vec<_> = parser::parse(input).try_collect()?;
+    ensure!(!measurements.is_empty(), "No sensors");
 
     let min_x = measurements
         .iter()
@@ -78,72 +158,467 @@ fn solve_part1(input: &str) -> usize {
         })
         .count();
 
-    result
+    Ok(result)
 }
 
-fn solve_part2(input: &str) -> Option<u64> {
-    let measurements = parse_input(input).collect_vec();
+/// Rotates a position by 45 degrees, into `u = x + y` and `v = x - y`. A manhattan distance
+/// diamond turns into an axis aligned square this way
+fn rotate(position: Vec2<i32>) -> Vec2<i32> {
+    Vec2::new(position.x + position.y, position.x - position.y)
+}
 
-    let max = 4_000_000;
+/// An axis aligned rectangle in rotated coordinates, with both bounds included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RotatedRect {
+    min: Vec2<i32>,
+    max: Vec2<i32>,
+}
 
-    let found_result = measurements.iter().find_map(|measurement| {
-        let Measurement {
-            sensor_position,
-            distance,
-            ..
-        } = *measurement;
+impl RotatedRect {
+    fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y
+    }
+
+    fn intersection(&self, other: &Self) -> Self {
+        Self {
+            min: Vec2::partial_max(self.min, other.min),
+            max: Vec2::partial_min(self.max, other.max),
+        }
+    }
+
+    /// The parts of `self` not covered by `other`, as at most four disjoint rectangles
+    fn subtract(&self, other: &Self) -> impl Iterator<Item = Self> {
+        let overlap = self.intersection(other);
+
+        let pieces = if overlap.is_empty() {
+            Either::Left([*self].into_iter())
+        } else {
+            Either::Right(
+                [
+                    // The full height strips left and right of the overlap
+                    Self {
+                        min: self.min,
+                        max: Vec2::new(overlap.min.x - 1, self.max.y),
+                    },
+                    Self {
+                        min: Vec2::new(overlap.max.x + 1, self.min.y),
+                        max: self.max,
+                    },
+                    // The parts above and below the overlap
+                    Self {
+                        min: Vec2::new(overlap.min.x, self.min.y),
+                        max: Vec2::new(overlap.max.x, overlap.min.y - 1),
+                    },
+                    Self {
+                        min: Vec2::new(overlap.min.x, overlap.max.y + 1),
+                        max: Vec2::new(overlap.max.x, self.max.y),
+                    },
+                ]
+                .into_iter(),
+            )
+        };
+
+        pieces.filter(|piece| !piece.is_empty())
+    }
 
-        let mut dx = 0;
+    /// Every position in the rectangle that also lies between `min` and `max` in normal coordinates
+    fn positions_in_box(self, min: Vec2<i32>, max: Vec2<i32>) -> impl Iterator<Item = Vec2<i32>> {
+        // The range of `u` for which the rectangle and the box overlap
+        let min_u = [
+            self.min.x,
+            min.x + min.y,
+            2 * min.x - self.max.y,
+            self.min.y + 2 * min.y,
+        ];
+        let max_u = [
+            self.max.x,
+            max.x + max.y,
+            2 * max.x - self.min.y,
+            self.max.y + 2 * max.y,
+        ];
+
+        let min_u = min_u.into_iter().max().unwrap();
+        let max_u = max_u.into_iter().min().unwrap();
+
+        (min_u..=max_u).flat_map(move |u| {
+            let min_v = self.min.y.max(2 * min.x - u).max(u - 2 * max.y);
+            let max_v = self.max.y.min(2 * max.x - u).min(u - 2 * min.y);
+
+            // Only positions where `u` and `v` have the same parity exist in normal coordinates
+            let min_v = min_v + (u - min_v).rem_euclid(2);
+
+            (min_v..=max_v)
+                .step_by(2)
+                .map(move |v| Vec2::new((u + v) / 2, (u - v) / 2))
+        })
+    }
+}
 
-        let clamp = |a: i32| a.clamp(0, max);
+/// A set of positions, stored as disjoint rectangles in rotated coordinates
+#[derive(Debug, Clone, Default)]
+struct Region {
+    rects: Vec<RotatedRect>,
+}
 
-        let vertical_range = clamp(sensor_position.y - distance as i32 + 1)
-            ..=clamp(sensor_position.y + distance as i32 + 1);
+impl Region {
+    /// Every position between `min` and `max`, and some outside of it
+    fn bounding(min: Vec2<i32>, max: Vec2<i32>) -> Self {
+        let rect = RotatedRect {
+            min: Vec2::new(min.x + min.y, min.x - max.y),
+            max: Vec2::new(max.x + max.y, max.x - min.y),
+        };
 
-        for y in vertical_range {
-            let targets = if dx == 0 {
-                Either::Left([Vec2::new(sensor_position.x, y)])
-            } else {
-                Either::Right([
-                    Vec2::new(sensor_position.x + dx, y),
-                    Vec2::new(sensor_position.x - dx, y),
-                ])
-            };
+        Self { rects: vec![rect] }
+    }
 
-            let target_position = targets
-                .into_iter()
-                .filter(|position| position.x >= 0 && position.x <= max)
-                .find(|&position| {
-                    measurements.iter().all(|measurement| {
-                        let distance = manhattan_distance(position, measurement.sensor_position);
+    /// The positions the sensor has ruled out
+    fn covered_by(measurement: &Measurement) -> Self {
+        let center = rotate(measurement.sensor_position);
+        let distance = measurement.distance as i32;
 
-                        let is_in_sensor_distance = distance <= measurement.distance;
+        let rect = RotatedRect {
+            min: center - distance,
+            max: center + distance,
+        };
 
-                        !is_in_sensor_distance
-                    })
-                });
+        Self { rects: vec![rect] }
+    }
 
-            if let Some(target_position) = target_position {
-                return Some(target_position.x as u64 * max as u64 + target_position.y as u64);
-            }
+    fn subtract(&self, other: &Self) -> Self {
+        let rects = other.rects.iter().fold(self.rects.clone(), |rects, b| {
+            rects.iter().flat_map(|a| a.subtract(b)).collect()
+        });
 
-            if y <= sensor_position.y {
-                dx += 1;
-            } else {
-                dx -= 1
-            }
+        Self { rects }
+    }
+
+    fn union(&self, other: &Self) -> Self {
+        let mut rects = self.rects.clone();
+        rects.extend(other.subtract(self).rects);
+
+        Self { rects }
+    }
+
+    fn positions_in_box(
+        &self,
+        min: Vec2<i32>,
+        max: Vec2<i32>,
+    ) -> impl Iterator<Item = Vec2<i32>> + '_ {
+        self.rects
+            .iter()
+            .flat_map(move |rect| rect.positions_in_box(min, max))
+    }
+}
+
+/// The positions no sensor has ruled out, covering every such position between `min` and `max`
+/// and possibly some outside of it
+fn uncovered_region(measurements: &[Measurement], min: Vec2<i32>, max: Vec2<i32>) -> Region {
+    let covered = measurements
+        .iter()
+        .map(Region::covered_by)
+        .fold(Region::default(), |covered, region| covered.union(&region));
+
+    Region::bounding(min, max).subtract(&covered)
+}
+
+/// Where the distress beacon could be, based on the positions no sensor has ruled out
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DistressBeacon {
+    Nowhere,
+    One(Vec2<i32>),
+    /// Only the first few positions, there might be far too many to list
+    Several(Vec<Vec2<i32>>),
+}
+
+impl DistressBeacon {
+    const MAX_LISTED: usize = 10;
+
+    fn positions(&self) -> &[Vec2<i32>] {
+        match self {
+            DistressBeacon::Nowhere => &[],
+            DistressBeacon::One(position) => slice::from_ref(position),
+            DistressBeacon::Several(positions) => positions,
+        }
+    }
+}
+
+/// Looks for positions between `(0, 0)` and `(max, max)` no sensor has ruled out, the puzzle
+/// promises there's exactly one
+fn find_distress_beacon(measurements: &[Measurement], max: i32) -> DistressBeacon {
+    let (min, max) = (Vec2::zero(), Vec2::broadcast(max));
+
+    // Stops looking after a few positions, however many there are
+    let positions = uncovered_region(measurements, min, max)
+        .positions_in_box(min, max)
+        .take(DistressBeacon::MAX_LISTED)
+        .collect_vec();
+
+    match positions[..] {
+        [] => DistressBeacon::Nowhere,
+        [position] => DistressBeacon::One(position),
+        _ => DistressBeacon::Several(positions),
+    }
+}
+
+fn tuning_frequency(position: Vec2<i32>) -> u64 {
+    position.x as u64 * 4_000_000 + position.y as u64
+}
+
+fn solve_part2(input: &str) -> Result<DistressBeacon> {
+    let measurements: Vec<_> = parser::parse(input).try_collect()?;
+
+    let max = 4_000_000;
+
+    Ok(find_distress_beacon(&measurements, max))
+}
+
+fn is_covered(measurements: &[Measurement], position: Vec2<i32>) -> bool {
+    measurements.iter().any(|measurement| {
+        manhattan_distance(position, measurement.sensor_position) <= measurement.distance
+    })
+}
+
+/// Draws sensors, beacons and the positions they rule out between `min` and `max`, like in the
+/// puzzle description
+fn render(measurements: &[Measurement], min: Vec2<i32>, max: Vec2<i32>) -> String {
+    (min.y..=max.y)
+        .map(|y| {
+            (min.x..=max.x)
+                .map(|x| {
+                    let position = Vec2::new(x, y);
+
+                    if measurements.iter().any(|m| m.sensor_position == position) {
+                        'S'
+                    } else if measurements.iter().any(|m| m.beacon_position == position) {
+                        'B'
+                    } else if is_covered(measurements, position) {
+                        '#'
+                    } else {
+                        '.'
+                    }
+                })
+                .collect::<String>()
+        })
+        .join("\n")
+}
+
+/// Scales the area from `(0, 0)` to `(max, max)` down to `resolution` pixels squared, shading
+/// each pixel by how many sensors cover its center. Possible distress beacons are marked in red
+fn render_overview(
+    measurements: &[Measurement],
+    max: i32,
+    resolution: usize,
+    distress_beacon: &DistressBeacon,
+) -> Image {
+    let mut image = Image::new(resolution, resolution);
+
+    let cell_size = (max as f64 + 1.0) / resolution as f64;
+    let to_position = |pixel: usize| ((pixel as f64 + 0.5) * cell_size) as i32;
+    let to_pixel = |position: i32| ((position as f64 / cell_size) as usize).min(resolution - 1);
+
+    image
+        .pixels
+        .indexed_iter_mut()
+        .par_bridge()
+        .for_each(|((x, y), pixel)| {
+            let position = Vec2::new(to_position(x), to_position(y));
+
+            let coverage = measurements
+                .iter()
+                .filter(|measurement| {
+                    manhattan_distance(position, measurement.sensor_position)
+                        <= measurement.distance
+                })
+                .count();
+
+            let brightness = (coverage * 48).min(255) as u8;
+            *pixel = Rgb::new(brightness / 2, brightness, brightness);
+        });
+
+    for measurement in measurements {
+        let Vec2 { x, y } = measurement.sensor_position;
+
+        if (0..=max).contains(&x) && (0..=max).contains(&y) {
+            image.set(Vec2::new(to_pixel(x), to_pixel(y)), Rgb::new(255, 255, 0));
         }
+    }
 
-        None
-    });
+    for distress_beacon in distress_beacon.positions() {
+        let center = distress_beacon.map(to_pixel).as_::<i32>();
 
-    return found_result;
+        // A small cross, so the beacon stands out even in large images
+        for offset in -2..=2 {
+            for position in [center + Vec2::new(offset, 0), center + Vec2::new(0, offset)] {
+                if position.x >= 0 && position.y >= 0 {
+                    let position = position.as_::<usize>();
+
+                    if position.x < resolution && position.y < resolution {
+                        image.set(position, Rgb::new(255, 0, 0));
+                    }
+                }
+            }
+        }
+    }
+
+    image
 }
 
-pub fn main() {
+pub fn main() -> Result<()> {
     let input = include_str!("../input/day15.txt");
 
-    execution_time(|| dbg!(solve_part1(input)));
+    let part_1 = execution_time(|| solve_part1(input))?;
+    dbg!(part_1);
+
+    let distress_beacon = execution_time(|| solve_part2(input))?;
+
+    match &distress_beacon {
+        DistressBeacon::One(position) => {
+            let part_2 = tuning_frequency(*position);
+            dbg!(part_2);
+        }
+        DistressBeacon::Nowhere => println!("Every position is covered by a sensor"),
+        DistressBeacon::Several(positions) => println!(
+            "Several positions aren't covered by any sensor, like {}",
+            positions.iter().join(", ")
+        ),
+    }
+
+    let measurements: Vec<_> = parser::parse(input).try_collect()?;
+    let mut args = env::args().skip(1);
+
+    match args.next().as_deref() {
+        Some("render") => {
+            let [min_x, min_y, max_x, max_y] = args
+                .map(|number| Ok(number.parse::<i32>()?))
+                .collect_array_ok()
+                .context("Expected the bounds as `min_x min_y max_x max_y`")?;
+
+            let min = Vec2::new(min_x, min_y);
+            let max = Vec2::new(max_x, max_y);
+
+            println!("{}", render(&measurements, min, max));
+        }
+        Some("overview") => {
+            let path = args.next().context("Expected a path for the image")?;
+            let max = 4_000_000;
+
+            render_overview(&measurements, max, 1000, &distress_beacon)
+                .save(path)
+                .context("Failed to save the overview")?;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+Sensor at x=2, y=18: closest beacon is at x=-2, y=15
+Sensor at x=9, y=16: closest beacon is at x=10, y=16
+Sensor at x=13, y=2: closest beacon is at x=15, y=3
+Sensor at x=12, y=14: closest beacon is at x=10, y=16
+Sensor at x=10, y=20: closest beacon is at x=10, y=16
+Sensor at x=14, y=17: closest beacon is at x=10, y=16
+Sensor at x=8, y=7: closest beacon is at x=2, y=10
+Sensor at x=2, y=0: closest beacon is at x=2, y=10
+Sensor at x=0, y=11: closest beacon is at x=2, y=10
+Sensor at x=20, y=14: closest beacon is at x=25, y=17
+Sensor at x=17, y=20: closest beacon is at x=21, y=22
+Sensor at x=16, y=7: closest beacon is at x=15, y=3
+Sensor at x=14, y=3: closest beacon is at x=15, y=3
+Sensor at x=20, y=1: closest beacon is at x=15, y=3";
+
+    fn measurements() -> Vec<Measurement> {
+        parser::parse(EXAMPLE).try_collect().unwrap()
+    }
+
+    #[test]
+    fn distress_beacon() {
+        assert_eq!(
+            find_distress_beacon(&measurements(), 20),
+            DistressBeacon::One(Vec2::new(14, 11))
+        );
+    }
 
-    execution_time(|| dbg!(solve_part2(input)));
+    #[test]
+    fn uncovered_positions_match_brute_force() {
+        let measurements = measurements();
+
+        for (min, max) in [
+            ((-10, -10), (40, 40)),
+            ((3, 7), (17, 12)),
+            ((-3, 2), (-3, 2)),
+        ] {
+            let (min, max) = (Vec2::from(min), Vec2::from(max));
+
+            let mut positions = uncovered_region(&measurements, min, max)
+                .positions_in_box(min, max)
+                .collect_vec();
+            positions.sort_by_key(|position| (position.x, position.y));
+
+            let expected = (min.x..=max.x)
+                .cartesian_product(min.y..=max.y)
+                .map(Vec2::from)
+                .filter(|&position| !is_covered(&measurements, position))
+                .collect_vec();
+
+            assert_eq!(positions, expected);
+        }
+    }
+
+    #[test]
+    fn several_or_no_uncovered_positions() {
+        let everywhere = find_distress_beacon(&[], 4_000_000);
+        assert_eq!(everywhere.positions().len(), DistressBeacon::MAX_LISTED);
+        assert!(matches!(everywhere, DistressBeacon::Several(_)));
+
+        let measurements = measurements();
+        let DistressBeacon::Several(positions) = find_distress_beacon(&measurements, 4_000_000)
+        else {
+            panic!("Expected several positions");
+        };
+        assert!(positions
+            .iter()
+            .all(|&position| !is_covered(&measurements, position)));
+
+        let covering = Measurement::new(Vec2::new(10, 10), Vec2::new(30, 30));
+        assert_eq!(
+            find_distress_beacon(&[covering], 20),
+            DistressBeacon::Nowhere
+        );
+    }
+
+    #[test]
+    fn no_sensors() {
+        for input in ["", "\n\n", "  \n"] {
+            assert_eq!(solve_part1(input).unwrap_err().to_string(), "No sensors");
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        let input = "Sensor at x=1, y=2: closest beacon is at x=3, y=4\n\
+            Sensor at x=1, y=2: closest beacon at x=3, y=4";
+
+        let error = parser::parse(input).find_map(Result::err).unwrap();
+
+        assert_eq!((error.line, error.column), (2, 36));
+    }
+
+    #[test]
+    fn parse_whitespace_and_negatives() {
+        let measurement =
+            parser::parse("  Sensor  at x = -2 , y=18:closest   beacon is at x=-2, y=15 ")
+                .next()
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(measurement.sensor_position, Vec2::new(-2, 18));
+        assert_eq!(measurement.beacon_position, Vec2::new(-2, 15));
+        assert_eq!(measurement.distance, 3);
+    }
 }
@@ -1,20 +1,92 @@
 use advent_of_code::execution_time;
+use advent_of_code::iterator_helpers::IteratorHelpers;
+use advent_of_code::windows::all_distinct;
 use anyhow::Result;
 use anyhow::{Context, Error};
-use byte_set::ByteSet;
+use std::collections::VecDeque;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
 
-pub fn find_index_with_distinct_characters(input: &str, amount: usize) -> Result<usize> {
-    input
-        .as_bytes()
-        .windows(amount)
-        .enumerate()
-        .find_map(|(index, slice)| {
-            let byte_set = ByteSet::from(slice);
+/// Finds every position in a stream right after `amount` distinct bytes, without reading the
+/// whole stream into memory. Like `ScanWindows`, an empty window never counts as a marker
+pub struct Markers<R: Read> {
+    bytes: io::Bytes<BufReader<R>>,
+    amount: usize,
+    window: VecDeque<u8>,
+    /// How often every byte occurs in the window
+    counts: [usize; 256],
+    /// How many different bytes occur more than once in the window
+    duplicates: usize,
+    position: usize,
+}
+
+impl<R: Read> Markers<R> {
+    pub fn new(reader: R, amount: usize) -> Self {
+        Self {
+            bytes: BufReader::new(reader).bytes(),
+            amount,
+            window: VecDeque::with_capacity(amount + 1),
+            counts: [0; 256],
+            duplicates: 0,
+            position: 0,
+        }
+    }
+}
+
+impl<R: Read> Iterator for Markers<R> {
+    type Item = io::Result<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.amount == 0 {
+            return None;
+        }
 
-            let len = byte_set.len();
+        loop {
+            let byte = match self.bytes.next()? {
+                Ok(byte) => byte,
+                Err(error) => return Some(Err(error)),
+            };
+
+            self.position += 1;
+
+            self.window.push_back(byte);
+            self.counts[byte as usize] += 1;
+
+            if self.counts[byte as usize] == 2 {
+                self.duplicates += 1;
+            }
+
+            if self.window.len() > self.amount {
+                let old_byte = self.window.pop_front().unwrap();
+                self.counts[old_byte as usize] -= 1;
+
+                if self.counts[old_byte as usize] == 1 {
+                    self.duplicates -= 1;
+                }
+            }
+
+            if self.window.len() == self.amount && self.duplicates == 0 {
+                return Some(Ok(self.position));
+            }
+        }
+    }
+}
 
-            (len == amount).then_some(index + amount)
-        })
+pub fn find_index_with_distinct_characters(input: &str, amount: usize) -> Result<usize> {
+    Markers::new(input.as_bytes(), amount)
+        .next()
+        .context("Something went wrong")?
+        .map_err(Error::from)
+}
+
+/// Same as `find_index_with_distinct_characters`, but for any characters rather than bytes. The
+/// index is counted in characters
+pub fn find_index_with_distinct_chars(input: &str, amount: usize) -> Result<usize> {
+    input
+        .chars()
+        .scan_windows(amount, all_distinct())
+        .next()
         .context("Something went wrong")
 }
 
@@ -35,5 +107,52 @@ pub fn main() -> Result<()> {
     let part_2 = execution_time(|| solve_part2(input))?;
     dbg!(part_2);
 
+    let part_2_chars = execution_time(|| find_index_with_distinct_chars(input, 14))?;
+    dbg!(part_2_chars);
+
+    // Streams a signal from a file, printing every message marker in it
+    if let Some(path) = env::args().nth(1) {
+        let file = File::open(path).context("Failed to open the signal")?;
+
+        for marker in Markers::new(file, 14) {
+            println!("{}", marker?);
+        }
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example() {
+        assert_eq!(solve_part1("mjqjpqmgbljsphdztnvjfqwrcgsmlb").unwrap(), 7);
+        assert_eq!(solve_part2("mjqjpqmgbljsphdztnvjfqwrcgsmlb").unwrap(), 19);
+        assert_eq!(solve_part1("bvwbjplbgvbhsrlpgdmjqwftvncz").unwrap(), 5);
+        assert_eq!(solve_part2("zcfzfwzzqfrljwzlrfnpqdbhtmscgvjw").unwrap(), 26);
+    }
+
+    #[test]
+    fn markers_match_window_scanner() {
+        let inputs = [
+            "",
+            "a",
+            "aaaa",
+            "abcabcd",
+            "nznrnfrfntjfmvfwmzdfjlvtqnbhcprsg",
+        ];
+
+        for input in inputs {
+            for amount in 0..6 {
+                let markers = Markers::new(input.as_bytes(), amount)
+                    .collect::<io::Result<Vec<_>>>()
+                    .unwrap();
+                let windows: Vec<_> = input.bytes().scan_windows(amount, all_distinct()).collect();
+
+                assert_eq!(markers, windows, "{input:?} with {amount} distinct bytes");
+            }
+        }
+    }
+}
@@ -1,92 +1,183 @@
 use advent_of_code::execution_time;
 use advent_of_code::iterator_helpers::IteratorHelpers;
-use anyhow::Result;
-use itertools::Either::{Left, Right};
+use advent_of_code::ppm::GrayImage;
+use anyhow::{ensure, Context, Result};
 use ndarray::{Array, Axis, Ix2};
-use take_until::TakeUntilExt;
+use std::env;
+use vek::Vec2;
 
+/// Indexed by `(x, y)`
 fn parse_height_map(input: &str) -> Result<Array<i32, Ix2>> {
+    let width = input.lines().next().context("Empty forest")?.len();
     let height = input.lines().count();
-    let width = input.lines().next().unwrap().len();
 
-    let input_digits = input
-        .lines()
-        .flat_map(|line| line.chars().map(|char| char.to_digit(10).unwrap() as i32));
+    let mut height_map = Array::zeros((width, height));
 
-    let height_map = Array::from_iter(input_digits).into_shape((width, height))?;
+    for (y, line) in input.lines().enumerate() {
+        ensure!(
+            line.len() == width,
+            "Row {y} is {} wide, expected {width}",
+            line.len()
+        );
+
+        for (x, char) in line.chars().enumerate() {
+            let tree_height = char
+                .to_digit(10)
+                .with_context(|| format!("Unknown character {char:?} at ({x}, {y})"))?;
+
+            height_map[(x, y)] = tree_height as i32;
+        }
+    }
 
     Ok(height_map)
 }
 
-fn solve_part1(input: &str) -> Result<i32> {
-    let height_map = parse_height_map(input)?;
-    let mut visited_items = Array::<bool, _>::default(height_map.raw_dim());
+/// What can be seen from every tree, and which trees can be seen from outside the forest
+struct Survey {
+    visible: Array<bool, Ix2>,
+    scenic_scores: Array<usize, Ix2>,
+}
 
-    let get_score_in_direction =
-        |axis: Axis, reverse: bool, visited: &mut Array<bool, Ix2>| -> i32 {
-            let mut count = 0;
+/// Sweeps every row and column in both directions, keeping a stack of the trees that still block
+/// the view. Trees shorter than the current one can't block anything behind it, so each tree is
+/// pushed and popped at most once per sweep
+fn survey(height_map: &Array<i32, Ix2>) -> Survey {
+    let mut visible = Array::<bool, _>::default(height_map.raw_dim());
+    let mut scenic_scores = Array::<usize, _>::ones(height_map.raw_dim());
 
-            for (axis_index, view) in height_map.axis_iter(axis).enumerate() {
-                let mut max_height = -1;
+    for axis in [Axis(0), Axis(1)] {
+        let lanes = height_map
+            .axis_iter(axis)
+            .zip(visible.axis_iter_mut(axis))
+            .zip(scenic_scores.axis_iter_mut(axis));
 
-                for (index, &target_height) in view.iter().enumerate().rev_if(reverse) {
-                    if target_height > max_height {
-                        max_height = target_height;
+        for ((heights, mut visible), mut scenic_scores) in lanes {
+            for reverse in [false, true] {
+                let mut stack: Vec<usize> = Vec::new();
 
-                        let mut visited_view = visited.index_axis_mut(axis, axis_index);
-                        let has_been_visited = visited_view.get_mut(index).unwrap();
+                for (index, &height) in heights.iter().enumerate().rev_if(reverse) {
+                    while stack.last().is_some_and(|&last| heights[last] < height) {
+                        stack.pop();
+                    }
 
-                        if !*has_been_visited {
-                            *has_been_visited = true;
-                            count += 1;
+                    let viewing_distance = match stack.last() {
+                        Some(&blocking_index) => blocking_index.abs_diff(index),
+                        None => {
+                            visible[index] = true;
+
+                            if reverse {
+                                heights.len() - 1 - index
+                            } else {
+                                index
+                            }
                         }
-                    }
+                    };
+
+                    scenic_scores[index] *= viewing_distance;
+                    stack.push(index);
                 }
             }
+        }
+    }
 
-            count
-        };
+    Survey {
+        visible,
+        scenic_scores,
+    }
+}
 
-    let score = get_score_in_direction(Axis(0), false, &mut visited_items)
-        + get_score_in_direction(Axis(1), false, &mut visited_items)
-        + get_score_in_direction(Axis(0), true, &mut visited_items)
-        + get_score_in_direction(Axis(1), true, &mut visited_items);
+impl Survey {
+    /// The tree with the highest scenic score
+    fn best_treehouse(&self) -> Vec2<usize> {
+        let ((x, y), _) = self
+            .scenic_scores
+            .indexed_iter()
+            .max_by_key(|&(_, &score)| score)
+            .unwrap();
 
-    Ok(score)
-}
-fn solve_part2(input: &str) -> Result<usize> {
-    let height_map = parse_height_map(input)?;
+        Vec2::new(x, y)
+    }
 
-    let scenic_score_side =
-        |target_tree: i32, axis: Axis, reversed: bool, axis_index: usize, index: usize| {
-            let axis_view = height_map.index_axis(axis, axis_index);
+    /// The forest with every tree hidden from outside replaced by `.`
+    fn render_visibility(&self, height_map: &Array<i32, Ix2>) -> String {
+        let (width, height) = height_map.dim();
 
-            let side_iterator = if reversed {
-                Left(axis_view.iter().take(index).rev())
-            } else {
-                Right(axis_view.iter().skip(index + 1))
-            };
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| match self.visible[(x, y)] {
+                        true => char::from_digit(height_map[(x, y)] as u32, 10).unwrap(),
+                        false => '.',
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
 
-            let score = side_iterator
-                .take_until(|&&other_tree| target_tree <= other_tree)
-                .count();
+    /// Every scenic score scaled to a digit from 0 to 9, with the best treehouse as `#`
+    fn render_scenic_scores(&self) -> String {
+        let (width, height) = self.scenic_scores.dim();
+        let max_score = self.scenic_scores.iter().max().copied().unwrap_or(0).max(1);
+        let best_treehouse = self.best_treehouse();
 
-            score
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| {
+                        if Vec2::new(x, y) == best_treehouse {
+                            return '#';
+                        }
+
+                        let digit = self.scenic_scores[(x, y)] * 9 / max_score;
+                        char::from_digit(digit as u32, 10).unwrap()
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Visible trees in white, hidden ones in black, and the best treehouse in gray
+    fn visibility_image(&self) -> GrayImage {
+        let mut image = GrayImage {
+            pixels: self.visible.mapv(|visible| if visible { 255 } else { 0 }),
         };
 
-    let scenic_score = |x: usize, y: usize, target_tree: i32| {
-        let score = scenic_score_side(target_tree, Axis(0), false, x, y)
-            * scenic_score_side(target_tree, Axis(0), true, x, y)
-            * scenic_score_side(target_tree, Axis(1), false, y, x)
-            * scenic_score_side(target_tree, Axis(1), true, y, x);
+        image.set(self.best_treehouse(), 128);
+        image
+    }
+
+    /// Brighter trees have a higher scenic score
+    fn scenic_score_image(&self) -> GrayImage {
+        let max_score = self.scenic_scores.iter().max().copied().unwrap_or(0).max(1);
 
-        score
-    };
+        GrayImage {
+            pixels: self
+                .scenic_scores
+                .mapv(|score| (score * 255 / max_score) as u8),
+        }
+    }
+}
+
+fn solve_part1(input: &str) -> Result<usize> {
+    let height_map = parse_height_map(input)?;
+    let visible_trees = survey(&height_map)
+        .visible
+        .iter()
+        .filter(|&&visible| visible)
+        .count();
 
-    let biggest_scenic_score = height_map
-        .indexed_iter()
-        .map(|((x, y), &target_tree)| scenic_score(x, y, target_tree))
+    Ok(visible_trees)
+}
+
+fn solve_part2(input: &str) -> Result<usize> {
+    let height_map = parse_height_map(input)?;
+    let biggest_scenic_score = survey(&height_map)
+        .scenic_scores
+        .iter()
         .max()
+        .copied()
         .unwrap();
 
     Ok(biggest_scenic_score)
@@ -101,5 +192,187 @@ pub fn main() -> Result<()> {
     let part_2 = execution_time(|| solve_part2(input))?;
     dbg!(part_2);
 
+    let height_map = parse_height_map(input)?;
+    let survey = survey(&height_map);
+
+    // Prints the grid, or saves it as a PGM image when a path is given
+    let mut args = env::args().skip(1);
+
+    match (args.next().as_deref(), args.next()) {
+        (Some("visibility"), None) => println!("{}", survey.render_visibility(&height_map)),
+        (Some("visibility"), Some(path)) => survey
+            .visibility_image()
+            .save(path)
+            .context("Failed to save the visibility map")?,
+        (Some("scenic"), None) => println!("{}", survey.render_scenic_scores()),
+        (Some("scenic"), Some(path)) => survey
+            .scenic_score_image()
+            .save(path)
+            .context("Failed to save the scenic score map")?,
+        _ => {}
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Either::{Left, Right};
+    use proptest::prelude::*;
+    use take_until::TakeUntilExt;
+
+    const EXAMPLE: &str = "\
+30373
+25512
+65332
+33549
+35390";
+
+    /// Reference solver for part 1, marks visible trees looking in from each side
+    fn solve_part1_reference(input: &str) -> Result<i32> {
+        let height_map = parse_height_map(input)?;
+        let mut visited_items = Array::<bool, _>::default(height_map.raw_dim());
+
+        let get_score_in_direction =
+            |axis: Axis, reverse: bool, visited: &mut Array<bool, Ix2>| -> i32 {
+                let mut count = 0;
+
+                for (axis_index, view) in height_map.axis_iter(axis).enumerate() {
+                    let mut max_height = -1;
+
+                    for (index, &target_height) in view.iter().enumerate().rev_if(reverse) {
+                        if target_height > max_height {
+                            max_height = target_height;
+
+                            let mut visited_view = visited.index_axis_mut(axis, axis_index);
+                            let has_been_visited = visited_view.get_mut(index).unwrap();
+
+                            if !*has_been_visited {
+                                *has_been_visited = true;
+                                count += 1;
+                            }
+                        }
+                    }
+                }
+
+                count
+            };
+
+        let score = get_score_in_direction(Axis(0), false, &mut visited_items)
+            + get_score_in_direction(Axis(1), false, &mut visited_items)
+            + get_score_in_direction(Axis(0), true, &mut visited_items)
+            + get_score_in_direction(Axis(1), true, &mut visited_items);
+
+        Ok(score)
+    }
+
+    /// Reference solver for part 2, walks outwards from every tree
+    fn solve_part2_reference(input: &str) -> Result<usize> {
+        let height_map = parse_height_map(input)?;
+
+        let scenic_score_side =
+            |target_tree: i32, axis: Axis, reversed: bool, axis_index: usize, index: usize| {
+                let axis_view = height_map.index_axis(axis, axis_index);
+
+                let side_iterator = if reversed {
+                    Left(axis_view.iter().take(index).rev())
+                } else {
+                    Right(axis_view.iter().skip(index + 1))
+                };
+
+                let score = side_iterator
+                    .take_until(|&&other_tree| target_tree <= other_tree)
+                    .count();
+
+                score
+            };
+
+        // Lanes along `Axis(0)` are columns picked by `x` and walked along `y`, lanes along
+        // `Axis(1)` are rows picked by `y` and walked along `x`
+        let scenic_score = |x: usize, y: usize, target_tree: i32| {
+            let score = scenic_score_side(target_tree, Axis(0), false, x, y)
+                * scenic_score_side(target_tree, Axis(0), true, x, y)
+                * scenic_score_side(target_tree, Axis(1), false, y, x)
+                * scenic_score_side(target_tree, Axis(1), true, y, x);
+
+            score
+        };
+
+        let biggest_scenic_score = height_map
+            .indexed_iter()
+            .map(|((x, y), &target_tree)| scenic_score(x, y, target_tree))
+            .max()
+            .unwrap();
+
+        Ok(biggest_scenic_score)
+    }
+
+    fn forest() -> impl Strategy<Value = String> {
+        (1..12usize, 1..12usize).prop_flat_map(|(width, height)| {
+            let row = prop::string::string_regex(&format!("[0-9]{{{width}}}")).unwrap();
+
+            prop::collection::vec(row, height).prop_map(|rows| rows.join("\n"))
+        })
+    }
+
+    #[test]
+    fn example() {
+        assert_eq!(solve_part1(EXAMPLE).unwrap(), 21);
+        assert_eq!(solve_part2(EXAMPLE).unwrap(), 8);
+
+        assert_eq!(solve_part1_reference(EXAMPLE).unwrap(), 21);
+        assert_eq!(solve_part2_reference(EXAMPLE).unwrap(), 8);
+    }
+
+    #[test]
+    fn rectangular_forest() {
+        let input = "\
+123
+456
+789
+012
+345";
+
+        let height_map = parse_height_map(input).unwrap();
+
+        assert_eq!(height_map.dim(), (3, 5));
+        assert_eq!(height_map[(0, 0)], 1);
+        assert_eq!(height_map[(2, 0)], 3);
+        assert_eq!(height_map[(1, 2)], 8);
+        assert_eq!(height_map[(0, 4)], 3);
+        assert_eq!(height_map[(2, 4)], 5);
+
+        assert_eq!(
+            solve_part1(input).unwrap(),
+            solve_part1_reference(input).unwrap() as usize
+        );
+        assert_eq!(
+            solve_part2(input).unwrap(),
+            solve_part2_reference(input).unwrap()
+        );
+    }
+
+    #[test]
+    fn malformed_forests() {
+        assert!(parse_height_map("").is_err());
+        assert!(parse_height_map("123\n45\n678").is_err());
+        assert!(parse_height_map("123\n4567\n").is_err());
+        assert!(parse_height_map("123\n4a6").is_err());
+        assert!(parse_height_map("1 3\n456").is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn survey_matches_reference(input in forest()) {
+            prop_assert_eq!(
+                solve_part1(&input).unwrap(),
+                solve_part1_reference(&input).unwrap() as usize
+            );
+            prop_assert_eq!(
+                solve_part2(&input).unwrap(),
+                solve_part2_reference(&input).unwrap()
+            );
+        }
+    }
+}
@@ -1,6 +1,8 @@
+use crate::windows::{ScanWindows, Window};
 use anyhow::{Context, Result};
 use itertools::Either;
 use std::array;
+use std::hash::Hash;
 use std::iter::Rev;
 
 pub trait IteratorHelpers: Iterator {
@@ -30,6 +32,16 @@ pub trait IteratorHelpers: Iterator {
             Either::Right(self)
         }
     }
+
+    /// Every position right after a window of `size` items matching the predicate
+    fn scan_windows<P>(self, size: usize, predicate: P) -> ScanWindows<Self, P>
+    where
+        Self: Sized,
+        Self::Item: Hash + Eq + Clone,
+        P: Fn(&Window<Self::Item>) -> bool,
+    {
+        ScanWindows::new(self, size, predicate)
+    }
 }
 
 impl<T: ?Sized> IteratorHelpers for T where T: Iterator {}
@@ -7,6 +7,8 @@ use std::time::Instant;
 use vek::Vec2;
 
 pub mod iterator_helpers;
+pub mod ppm;
+pub mod windows;
 
 pub fn execution_time<T>(runner: impl Fn() -> T) -> T {
     let now = Instant::now();
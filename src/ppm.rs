@@ -0,0 +1,80 @@
+use ndarray::Array2;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use vek::{Rgb, Vec2};
+
+/// A pixel format of the binary netpbm files
+pub trait Pixel: Copy + Default {
+    /// The magic number starting the file
+    const MAGIC: &'static str;
+
+    fn write(self, writer: &mut impl Write) -> io::Result<()>;
+}
+
+/// Written as a PPM file
+impl Pixel for Rgb<u8> {
+    const MAGIC: &'static str = "P6";
+
+    fn write(self, writer: &mut impl Write) -> io::Result<()> {
+        let Rgb { r, g, b } = self;
+        writer.write_all(&[r, g, b])
+    }
+}
+
+/// Written as a PGM file
+impl Pixel for u8 {
+    const MAGIC: &'static str = "P5";
+
+    fn write(self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&[self])
+    }
+}
+
+/// An image indexed by `(x, y)`, which can be written as a binary PPM or PGM file depending on
+/// its pixels
+pub struct Image<P = Rgb<u8>> {
+    pub pixels: Array2<P>,
+}
+
+pub type GrayImage = Image<u8>;
+
+impl<P: Pixel> Image<P> {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            pixels: Array2::default((width, height)),
+        }
+    }
+
+    pub fn size(&self) -> Vec2<usize> {
+        Vec2::from(self.pixels.dim())
+    }
+
+    pub fn set(&mut self, position: Vec2<usize>, color: P) {
+        self.pixels[position.into_tuple()] = color;
+    }
+
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        let Vec2 {
+            x: width,
+            y: height,
+        } = self.size();
+
+        write!(writer, "{}\n{width} {height}\n255\n", P::MAGIC)?;
+
+        for y in 0..height {
+            for x in 0..width {
+                self.pixels[(x, y)].write(&mut writer)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+}
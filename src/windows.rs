@@ -0,0 +1,265 @@
+use hashbrown::{HashMap, HashSet};
+use std::collections::VecDeque;
+use std::hash::Hash;
+
+/// The last few items of a stream, along with how often each of them occurs
+pub struct Window<T> {
+    items: VecDeque<T>,
+    counts: HashMap<T, usize>,
+}
+
+impl<T: Hash + Eq + Clone> Window<T> {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            items: VecDeque::with_capacity(capacity),
+            counts: HashMap::with_capacity(capacity),
+        }
+    }
+
+    fn push(&mut self, item: T) {
+        *self.counts.entry(item.clone()).or_default() += 1;
+        self.items.push_back(item);
+    }
+
+    fn pop(&mut self) {
+        let Some(item) = self.items.pop_front() else {
+            return;
+        };
+
+        let count = self.counts.get_mut(&item).unwrap();
+        *count -= 1;
+
+        if *count == 0 {
+            self.counts.remove(&item);
+        }
+    }
+
+    pub fn items(&self) -> &VecDeque<T> {
+        &self.items
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn count(&self, item: &T) -> usize {
+        self.counts.get(item).copied().unwrap_or(0)
+    }
+
+    /// How many different items are in the window
+    pub fn distinct(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// How many items repeat one that occurs earlier in the window
+    pub fn duplicates(&self) -> usize {
+        self.len() - self.distinct()
+    }
+}
+
+pub fn all_distinct<T: Hash + Eq + Clone>() -> impl Fn(&Window<T>) -> bool {
+    |window| window.duplicates() == 0
+}
+
+pub fn at_most_duplicates<T: Hash + Eq + Clone>(amount: usize) -> impl Fn(&Window<T>) -> bool {
+    move |window| window.duplicates() <= amount
+}
+
+pub fn contains_all<T: Hash + Eq + Clone>(required: HashSet<T>) -> impl Fn(&Window<T>) -> bool {
+    move |window| required.iter().all(|item| window.count(item) > 0)
+}
+
+/// Slides a window of `size` items over a stream, yielding how many items have been read
+/// whenever a full window matches the predicate. Empty windows never match
+pub struct ScanWindows<I: Iterator, P> {
+    items: I,
+    size: usize,
+    predicate: P,
+    window: Window<I::Item>,
+    position: usize,
+}
+
+impl<I, P> ScanWindows<I, P>
+where
+    I: Iterator,
+    I::Item: Hash + Eq + Clone,
+    P: Fn(&Window<I::Item>) -> bool,
+{
+    pub fn new(items: I, size: usize, predicate: P) -> Self {
+        Self {
+            items,
+            size,
+            predicate,
+            window: Window::with_capacity(size + 1),
+            position: 0,
+        }
+    }
+}
+
+impl<I, P> Iterator for ScanWindows<I, P>
+where
+    I: Iterator,
+    I::Item: Hash + Eq + Clone,
+    P: Fn(&Window<I::Item>) -> bool,
+{
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.size == 0 {
+            return None;
+        }
+
+        loop {
+            self.window.push(self.items.next()?);
+            self.position += 1;
+
+            if self.window.len() > self.size {
+                self.window.pop();
+            }
+
+            if self.window.len() == self.size && (self.predicate)(&self.window) {
+                return Some(self.position);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iterator_helpers::IteratorHelpers;
+    use proptest::prelude::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    enum Token {
+        Word(u8),
+        Comma,
+        Newline,
+    }
+
+    fn token() -> impl Strategy<Value = Token> {
+        prop_oneof![
+            (0..4u8).prop_map(Token::Word),
+            Just(Token::Comma),
+            Just(Token::Newline),
+        ]
+    }
+
+    /// Every position after a window of `size` items for which `predicate` holds on the slice
+    fn brute_force<T>(items: &[T], size: usize, predicate: impl Fn(&[T]) -> bool) -> Vec<usize> {
+        if size == 0 {
+            return Vec::new();
+        }
+
+        items
+            .windows(size)
+            .enumerate()
+            .filter(|(_, window)| predicate(window))
+            .map(|(index, _)| index + size)
+            .collect()
+    }
+
+    fn distinct<T: Hash + Eq>(window: &[T]) -> usize {
+        window.iter().collect::<HashSet<_>>().len()
+    }
+
+    fn check_predicates<T: Hash + Eq + Clone + std::fmt::Debug>(
+        items: &[T],
+        size: usize,
+        duplicates: usize,
+        required: &HashSet<T>,
+    ) {
+        let scan = |predicate: &dyn Fn(&Window<T>) -> bool| {
+            items
+                .iter()
+                .cloned()
+                .scan_windows(size, predicate)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            scan(&all_distinct()),
+            brute_force(items, size, |window| distinct(window) == window.len())
+        );
+        assert_eq!(
+            scan(&at_most_duplicates(duplicates)),
+            brute_force(items, size, |window| {
+                window.len() - distinct(window) <= duplicates
+            })
+        );
+        assert_eq!(
+            scan(&contains_all(required.clone())),
+            brute_force(items, size, |window| {
+                required.iter().all(|item| window.contains(item))
+            })
+        );
+    }
+
+    #[test]
+    fn example_markers() {
+        let markers = |input: &str, size| {
+            input
+                .chars()
+                .scan_windows(size, all_distinct())
+                .next()
+                .unwrap()
+        };
+
+        assert_eq!(markers("mjqjpqmgbljsphdztnvjfqwrcgsmlb", 4), 7);
+        assert_eq!(markers("mjqjpqmgbljsphdztnvjfqwrcgsmlb", 14), 19);
+        assert_eq!(markers("nznrnfrfntjfmvfwmzdfjlvtqnbhcprsg", 4), 10);
+    }
+
+    #[test]
+    fn window_counts() {
+        let mut window = Window::with_capacity(3);
+
+        for item in "abca".chars() {
+            window.push(item);
+        }
+        window.pop();
+
+        assert_eq!(window.items(), &VecDeque::from(['b', 'c', 'a']));
+        assert_eq!((window.distinct(), window.duplicates()), (3, 0));
+        assert_eq!(window.count(&'a'), 1);
+
+        window.push('c');
+
+        assert_eq!(
+            (window.len(), window.distinct(), window.duplicates()),
+            (4, 3, 1)
+        );
+        assert_eq!(window.count(&'c'), 2);
+    }
+
+    proptest! {
+        #[test]
+        fn chars_match_brute_force(
+            items in "[a-e]{0,40}",
+            size in 0..8usize,
+            duplicates in 0..3usize,
+            required in prop::collection::hash_set(prop::char::range('a', 'e'), 0..3),
+        ) {
+            let items: Vec<char> = items.chars().collect();
+            let required: HashSet<char> = required.into_iter().collect();
+
+            check_predicates(&items, size, duplicates, &required);
+        }
+
+        #[test]
+        fn tokens_match_brute_force(
+            items in prop::collection::vec(token(), 0..40),
+            size in 0..8usize,
+            duplicates in 0..3usize,
+            required in prop::collection::hash_set(token(), 0..3),
+        ) {
+            let required: HashSet<Token> = required.into_iter().collect();
+
+            check_predicates(&items, size, duplicates, &required);
+        }
+    }
+}